@@ -0,0 +1,387 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use lsp_types::Diagnostic;
+use lsp_types::Url;
+use miette::miette;
+
+use crate::discover;
+use crate::strip_path_prefix;
+use crate::EnvVar;
+
+/// Container runtimes to look for in `$PATH`, in order of preference.
+const RUNTIMES: &[&str] = &["docker", "podman"];
+
+/// Where the project is mounted in the container.
+pub const PROJECT_MOUNT: &str = "/workspace";
+
+/// A `lua-language-server` container for `--docker`, and the host paths mounted into it.
+#[derive(Debug)]
+pub struct Container {
+    runtime: PathBuf,
+    image: String,
+    /// Used to kill the container, since killing the runtime's client doesn't stop it.
+    name: String,
+    /// Host paths and where they're mounted, most specific first.
+    mounts: Vec<Mount>,
+    /// The container's memory limit in bytes, for `--memory-limit`.
+    pub memory_limit: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Mount {
+    host: PathBuf,
+    container: PathBuf,
+    read_only: bool,
+}
+
+impl Container {
+    /// Find a container runtime, `docker` or `podman`, unless one is given.
+    pub fn new(runtime: Option<&Path>, image: &str) -> miette::Result<Self> {
+        let runtime = match runtime {
+            Some(runtime) => discover::find_executable(runtime).ok_or_else(|| {
+                miette!("Container runtime not found: {}", runtime.display())
+            })?,
+            None => RUNTIMES
+                .iter()
+                .find_map(|name| discover::find_executable(Path::new(name)))
+                .ok_or_else(|| {
+                    miette!(
+                        help = "Install Docker or Podman, or pass `--container-runtime`",
+                        "`--docker` needs a container runtime, but neither `docker` nor `podman` is in `$PATH`"
+                    )
+                })?,
+        };
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+        Ok(Self {
+            runtime,
+            image: image.to_owned(),
+            name: format!("lualscheck-{}-{nanos:x}", std::process::id()),
+            mounts: Vec::new(),
+            memory_limit: None,
+        })
+    }
+
+    pub fn runtime(&self) -> &Path {
+        &self.runtime
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Bind-mount a host path into the container.
+    ///
+    /// Fails if the path has a comma, which would split the `--mount` option's
+    /// comma-separated fields.
+    pub fn mount(&mut self, host: &Path, container: &str, read_only: bool) -> miette::Result<()> {
+        if host.as_os_str().to_string_lossy().contains(',') {
+            return Err(miette!(
+                help = "Rename the directory, or pass a path without a comma",
+                "`--docker` can't mount a path containing a comma: {}",
+                host.display()
+            ));
+        }
+        self.mounts.push(Mount {
+            host: host.to_owned(),
+            container: PathBuf::from(container),
+            read_only,
+        });
+        self.mounts
+            .sort_by_key(|mount| std::cmp::Reverse(mount.host.components().count()));
+        Ok(())
+    }
+
+    /// Translate a host path to where it's mounted in the container.
+    pub fn to_container(&self, host: &Path) -> miette::Result<OsString> {
+        self.mounts
+            .iter()
+            .find_map(|mount| {
+                let relative = strip_path_prefix(host, &mount.host)?;
+                Some(join_container(&mount.container, &relative))
+            })
+            .ok_or_else(|| {
+                miette!(
+                    "{} isn't mounted in the `--docker` container",
+                    host.display()
+                )
+            })
+    }
+
+    /// Translate a path in the container to the host path mounted there, if any.
+    ///
+    /// The most specific mount wins when mounts are nested in the container.
+    pub fn to_host(&self, container: &Path) -> Option<PathBuf> {
+        self.mounts
+            .iter()
+            .filter_map(|mount| {
+                let relative = container.strip_prefix(&mount.container).ok()?;
+                Some((mount, relative))
+            })
+            .max_by_key(|(mount, _)| mount.container.components().count())
+            .map(|(mount, relative)| {
+                if relative.as_os_str().is_empty() {
+                    mount.host.clone()
+                } else {
+                    mount.host.join(relative)
+                }
+            })
+    }
+
+    /// Translate `file://` URIs in the container to host URIs, leaving others unchanged.
+    pub fn uri_to_host(&self, uri: &Url) -> Option<Url> {
+        if uri.scheme() != "file" {
+            return None;
+        }
+        // Container paths are always Unix paths, even if the host's aren't.
+        let path = percent_decode(uri.path());
+        let host = self.to_host(Path::new(&path))?;
+        Url::from_file_path(host).ok()
+    }
+
+    /// Translate the URIs in the server's diagnostics from container paths to host paths.
+    pub fn diagnostics_to_host(
+        &self,
+        diagnostics: BTreeMap<String, Vec<Diagnostic>>,
+    ) -> BTreeMap<String, Vec<Diagnostic>> {
+        diagnostics
+            .into_iter()
+            .map(|(uri, mut diagnostics)| {
+                for diagnostic in &mut diagnostics {
+                    for information in diagnostic.related_information.iter_mut().flatten() {
+                        if let Some(host) = self.uri_to_host(&information.location.uri) {
+                            information.location.uri = host;
+                        }
+                    }
+                }
+                let uri = Url::parse(&uri)
+                    .ok()
+                    .and_then(|parsed| self.uri_to_host(&parsed))
+                    .map(String::from)
+                    .unwrap_or(uri);
+                (uri, diagnostics)
+            })
+            .collect()
+    }
+
+    /// A `run` command for the container, running `program` with the container's mounts.
+    ///
+    /// The project mount is the working directory.
+    pub fn command(&self, program: &Path, envs: &[EnvVar]) -> Command {
+        let mut cmd = self.run();
+        cmd.arg("--name").arg(&self.name);
+        cmd.arg("--workdir").arg(PROJECT_MOUNT);
+
+        #[cfg(unix)]
+        if self
+            .runtime
+            .file_stem()
+            .is_some_and(|stem| stem == "docker")
+        {
+            // Write diagnostics and caches as the current user, not root. Rootless Podman
+            // already maps root to the current user.
+            // SAFETY: `getuid` and `getgid` have no preconditions.
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            cmd.arg("--user").arg(format!("{uid}:{gid}"));
+        }
+
+        for mount in &self.mounts {
+            let mut volume = OsString::from("type=bind,source=");
+            volume.push(&mount.host);
+            volume.push(",target=");
+            volume.push(&mount.container);
+            if mount.read_only {
+                volume.push(",readonly");
+            }
+            cmd.arg("--mount").arg(volume);
+        }
+        for env in envs {
+            cmd.arg("--env").arg(format!("{}={}", env.key, env.value));
+        }
+        cmd.arg("--entrypoint").arg(program);
+        cmd.arg(&self.image);
+        cmd
+    }
+
+    /// A `run` command for `program` in the container, without any mounts, to check its
+    /// `--version`.
+    pub fn version_command(&self, program: &Path) -> Command {
+        let mut cmd = self.run();
+        cmd.arg("--entrypoint").arg(program);
+        cmd.arg(&self.image);
+        cmd
+    }
+
+    fn run(&self) -> Command {
+        let mut cmd = Command::new(&self.runtime);
+        // `--init` runs a minimal init process which forwards signals to the server, which
+        // otherwise ignores them as PID 1.
+        cmd.args(["run", "--rm", "--init", "--network=none"]);
+        if let Some(memory_limit) = self.memory_limit {
+            cmd.arg(format!("--memory={memory_limit}b"));
+        }
+        cmd
+    }
+
+    /// Stop the container if it's still running, since killing the runtime's client
+    /// doesn't.
+    pub fn kill(&self) {
+        let result = Command::new(&self.runtime)
+            .arg("kill")
+            .arg(&self.name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if let Err(err) = result {
+            log::debug!("Failed to kill container {}: {err}", self.name);
+        }
+    }
+}
+
+/// Join a host-relative path onto a container path with `/`, whatever the host's separator.
+fn join_container(base: &Path, relative: &Path) -> OsString {
+    let mut joined = base.as_os_str().to_owned();
+    for component in relative.components() {
+        joined.push("/");
+        joined.push(component.as_os_str());
+    }
+    joined
+}
+
+/// Decode `%XX` escapes in a URI path.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = path
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container() -> Container {
+        Container {
+            runtime: PathBuf::from("docker"),
+            image: "lua-language-server".to_owned(),
+            name: "lualscheck-test".to_owned(),
+            mounts: Vec::new(),
+            memory_limit: None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_to_host_nested_mounts() {
+        let mut container = container();
+        container
+            .mount(Path::new("/home/jane/project"), PROJECT_MOUNT, true)
+            .unwrap();
+        // Mounted inside the project mount, from somewhere else on the host.
+        container
+            .mount(Path::new("/tmp/meta"), "/workspace/.meta", false)
+            .unwrap();
+        container
+            .mount(
+                Path::new("/home/jane/project/vendor/lib"),
+                "/lualscheck/lib",
+                true,
+            )
+            .unwrap();
+        assert_eq!(
+            container.to_host(Path::new("/workspace/init.lua")),
+            Some(PathBuf::from("/home/jane/project/init.lua"))
+        );
+        assert_eq!(
+            container.to_host(Path::new("/workspace/.meta/vim.lua")),
+            Some(PathBuf::from("/tmp/meta/vim.lua"))
+        );
+        assert_eq!(
+            container.to_host(Path::new("/lualscheck/lib/util.lua")),
+            Some(PathBuf::from("/home/jane/project/vendor/lib/util.lua"))
+        );
+        assert_eq!(
+            container.to_host(Path::new("/workspace")),
+            Some(PathBuf::from("/home/jane/project"))
+        );
+        // Only whole components match.
+        assert_eq!(container.to_host(Path::new("/workspace2/init.lua")), None);
+        assert_eq!(
+            container.to_host(Path::new("/usr/share/lua/init.lua")),
+            None
+        );
+        // The host path nested in the project maps to the more specific mount, too.
+        assert_eq!(
+            container
+                .to_container(Path::new("/home/jane/project/vendor/lib/util.lua"))
+                .unwrap(),
+            "/lualscheck/lib/util.lua"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_uri_to_host_percent_encoded() {
+        let mut container = container();
+        container
+            .mount(Path::new("/home/jane/my project"), PROJECT_MOUNT, true)
+            .unwrap();
+        let uri = |uri: &str| container.uri_to_host(&Url::parse(uri).unwrap());
+        assert_eq!(
+            uri("file:///workspace/lua/%E8%AF%8A%E6%96%AD%20x.lua"),
+            Some(Url::from_file_path("/home/jane/my project/lua/诊断 x.lua").unwrap())
+        );
+        assert_eq!(
+            uri("file:///workspace/100%25.lua"),
+            Some(Url::from_file_path("/home/jane/my project/100%.lua").unwrap())
+        );
+        assert_eq!(uri("file:///elsewhere/init.lua"), None);
+        assert_eq!(uri("untitled:Untitled-1"), None);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("/a%20b/c%2Cd"), "/a b/c,d");
+        assert_eq!(percent_decode("/%e8%af%8a"), "/诊");
+        // Malformed escapes are left as they are.
+        assert_eq!(percent_decode("/100%/%zz/%4"), "/100%/%zz/%4");
+    }
+
+    #[test]
+    fn test_mount_rejects_commas() {
+        let mut container = container();
+        let err = container
+            .mount(Path::new("/home/jane/a,b"), PROJECT_MOUNT, true)
+            .unwrap_err();
+        assert!(err.to_string().contains("comma"), "{err}");
+        assert!(container.mounts.is_empty());
+    }
+}
@@ -0,0 +1,854 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+
+use clap::ValueEnum;
+use lsp_types::CodeDescription;
+use lsp_types::Diagnostic;
+use lsp_types::DiagnosticRelatedInformation;
+use lsp_types::DiagnosticSeverity;
+use lsp_types::DiagnosticTag;
+use lsp_types::Location;
+use lsp_types::NumberOrString;
+use lsp_types::Position;
+use lsp_types::Range;
+use lsp_types::Url;
+use miette::miette;
+use miette::Context;
+use miette::IntoDiagnostic;
+use serde::de::DeserializeOwned;
+use serde::de::DeserializeSeed;
+use serde::de::Error;
+use serde::de::IgnoredAny;
+use serde::de::MapAccess;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde_json::Value;
+
+use crate::Severity;
+
+/// How many lines of the JSON for a diagnostic which fails to deserialize to show.
+const SNIPPET_LINES: usize = 20;
+
+/// Diagnostics by file URI, as written by `lua-language-server --check`.
+pub type Diagnostics = BTreeMap<String, Vec<Diagnostic>>;
+
+/// Which diagnostics to keep while reading a diagnostics file.
+///
+/// Everything else is skipped without being deserialized, so that huge files, mostly full of
+/// diagnostics for libraries outside the project, don't have to fit in memory.
+pub struct Filter<'a> {
+    /// Whether to keep a file's diagnostics, by its URI.
+    pub file: &'a dyn Fn(&str) -> bool,
+    pub diagnostic: &'a dyn Fn(&Diagnostic) -> bool,
+    /// Fail on diagnostics which don't deserialize, rather than skipping them with a warning.
+    pub strict: bool,
+}
+
+/// Read a diagnostics file, a streaming equivalent of [`parse`].
+pub fn read(
+    path: &Path,
+    filter: &Filter<'_>,
+    server_version: Option<&str>,
+) -> miette::Result<Diagnostics> {
+    let source = format!("{path:?}");
+    let mut reader = File::open(path)
+        .map(BufReader::new)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read diagnostics file: {path:?}"))?;
+
+    let start = reader
+        .fill_buf()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read diagnostics file: {path:?}"))?;
+    if start.starts_with(b"\xFF\xFE") || start.starts_with(b"\xFE\xFF") {
+        // Rare enough that it isn't worth decoding UTF-16 incrementally.
+        let mut contents = Vec::new();
+        reader
+            .read_to_end(&mut contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read diagnostics file: {path:?}"))?;
+        return parse(contents, &source, filter, server_version);
+    }
+    let bom = if start.starts_with(b"\xEF\xBB\xBF") {
+        3
+    } else {
+        0
+    };
+    reader.consume(bom);
+
+    let failure = Failure::default();
+    let mut deserializer = serde_json::Deserializer::from_reader(Utf8Reader::new(reader, bom));
+    let result = FilteredDiagnostics {
+        filter,
+        failure: &failure,
+    }
+    .deserialize(&mut deserializer)
+    .and_then(|diagnostics| deserializer.end().map(|()| diagnostics));
+    match result {
+        Ok(diagnostics) => Ok(diagnostics),
+        // Some server versions write an empty file if there are no problems.
+        Err(err) if err.is_eof() && is_blank(path) => Ok(Diagnostics::new()),
+        Err(err) if err.is_io() => {
+            let err = std::io::Error::from(err);
+            match err
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<InvalidUtf8>())
+            {
+                Some(InvalidUtf8 { offset }) => Err(invalid_utf8(*offset, &source)),
+                None => Err(err)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to read diagnostics file: {path:?}")),
+            }
+        }
+        Err(err) => {
+            // Read the file again to find the diagnostic which failed, rather than keeping
+            // every diagnostic's JSON around in case one does.
+            let element = failure.uri.borrow().as_ref().and_then(|uri| {
+                let mut reader = BufReader::new(File::open(path).ok()?);
+                reader.consume(bom);
+                find_element(
+                    &mut serde_json::Deserializer::from_reader(reader),
+                    uri,
+                    failure.index.get(),
+                )
+            });
+            Err(deserialize_error(
+                err,
+                &source,
+                &failure,
+                element,
+                server_version,
+            ))
+        }
+    }
+}
+
+/// Parse diagnostics JSON, mapping file URIs to their diagnostics.
+///
+/// `source` names where the JSON came from, and `server_version` the server which wrote it,
+/// if any, for errors.
+pub fn parse(
+    contents: Vec<u8>,
+    source: &str,
+    filter: &Filter<'_>,
+    server_version: Option<&str>,
+) -> miette::Result<Diagnostics> {
+    let contents = decode_bom(contents)
+        .wrap_err_with(|| format!("Failed to decode diagnostics from {source}"))?;
+    if let Err(err) = std::str::from_utf8(&contents) {
+        return Err(invalid_utf8(err.valid_up_to() as u64, source));
+    }
+    // Some server versions write an empty file if there are no problems.
+    if contents.trim_ascii().is_empty() {
+        return Ok(Diagnostics::new());
+    }
+    let failure = Failure::default();
+    let mut deserializer = serde_json::Deserializer::from_slice(&contents);
+    FilteredDiagnostics {
+        filter,
+        failure: &failure,
+    }
+    .deserialize(&mut deserializer)
+    .and_then(|diagnostics| deserializer.end().map(|()| diagnostics))
+    .map_err(|err| {
+        let element = failure.uri.borrow().as_ref().and_then(|uri| {
+            find_element(
+                &mut serde_json::Deserializer::from_slice(&contents),
+                uri,
+                failure.index.get(),
+            )
+        });
+        deserialize_error(err, source, &failure, element, server_version)
+    })
+}
+
+/// Where deserializing a diagnostics file failed.
+#[derive(Debug, Default)]
+struct Failure {
+    /// The URI of the file whose diagnostics failed.
+    uri: RefCell<Option<String>>,
+    /// The index of the diagnostic which failed, if the file's value is an array.
+    index: Cell<Option<usize>>,
+}
+
+/// Describe a deserialization error with the path to the value which failed, like
+/// `"file:///foo.lua"[17].range.start.line`, and the JSON of the diagnostic containing it.
+fn deserialize_error(
+    err: serde_json::Error,
+    source: &str,
+    failure: &Failure,
+    element: Option<Value>,
+    server_version: Option<&str>,
+) -> miette::Report {
+    let Some(uri) = failure.uri.borrow().clone() else {
+        return miette!("Failed to deserialize diagnostics from {source}: {err}");
+    };
+    let mut path = format!("{uri:?}");
+    if let Some(index) = failure.index.get() {
+        path.push_str(&format!("[{index}]"));
+        if let Some(field) = element.as_ref().and_then(locate_diagnostic_error) {
+            path.push_str(&field);
+        }
+    }
+
+    let mut help = String::new();
+    if let Some(element) = &element {
+        let json = serde_json::to_string_pretty(element).unwrap_or_default();
+        let mut lines = json.lines();
+        let snippet = lines
+            .by_ref()
+            .take(SNIPPET_LINES)
+            .collect::<Vec<_>>()
+            .join("\n");
+        help.push_str(&snippet);
+        if lines.next().is_some() {
+            help.push_str("\n…");
+        }
+        help.push_str("\n\n");
+    }
+    help.push_str("This may be a bug in lualscheck or ");
+    match server_version {
+        Some(version) => help.push_str(&format!("lua-language-server {version}")),
+        None => help.push_str("the tool which wrote the diagnostics"),
+    }
+    miette!(
+        help = help,
+        "Failed to deserialize diagnostics from {source} at `{path}`: {err}"
+    )
+}
+
+/// Find the JSON for a file's diagnostic, or all of its diagnostics without an `index`.
+fn find_element<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    uri: &str,
+    index: Option<usize>,
+) -> Option<Value> {
+    FindElement { uri, index }
+        .deserialize(deserializer)
+        .ok()
+        .flatten()
+}
+
+struct FindElement<'a> {
+    uri: &'a str,
+    index: Option<usize>,
+}
+
+impl<'de> DeserializeSeed<'de> for FindElement<'_> {
+    type Value = Option<Value>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FindElement<'_> {
+    type Value = Option<Value>;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(EXPECTED_SHAPES)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut found = None;
+        while let Some(file) = seq.next_element::<PublishedFile>()? {
+            if found.is_none() && file.uri == self.uri {
+                let mut diagnostics = Value::Array(file.diagnostics);
+                found = match self.index {
+                    Some(index) => diagnostics.get_mut(index).map(Value::take),
+                    None => Some(diagnostics),
+                };
+            }
+        }
+        Ok(found)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut found = None;
+        while let Some(uri) = map.next_key::<String>()? {
+            if found.is_some() || uri != self.uri {
+                map.next_value::<IgnoredAny>()?;
+                continue;
+            }
+            let mut value = map.next_value::<Value>()?;
+            found = match self.index {
+                Some(index) => value.get_mut(index).map(Value::take),
+                None => Some(value),
+            };
+        }
+        Ok(found)
+    }
+}
+
+/// Whether a JSON value deserializes as a `T`.
+fn fits<T: DeserializeOwned>(value: &Value) -> bool {
+    T::deserialize(value).is_ok()
+}
+
+/// The path to the field of a diagnostic's JSON which doesn't deserialize, like
+/// `.range.start.line`.
+fn locate_diagnostic_error(diagnostic: &Value) -> Option<String> {
+    if fits::<Diagnostic>(diagnostic) {
+        return None;
+    }
+    if !diagnostic.is_object() {
+        return Some(String::new());
+    }
+    if let Some(field) = locate_range_error(&diagnostic["range"]) {
+        return Some(format!(".range{field}"));
+    }
+    type Fits = fn(&Value) -> bool;
+    let fields: [(&str, Fits); 6] = [
+        ("severity", fits::<Option<DiagnosticSeverity>>),
+        ("code", fits::<Option<NumberOrString>>),
+        ("codeDescription", fits::<Option<CodeDescription>>),
+        ("source", fits::<Option<String>>),
+        ("message", fits::<String>),
+        ("tags", fits::<Option<Vec<DiagnosticTag>>>),
+    ];
+    if let Some((field, _)) = fields
+        .iter()
+        .find(|(field, fits)| !fits(&diagnostic[*field]))
+    {
+        return Some(format!(".{field}"));
+    }
+    let related = &diagnostic["relatedInformation"];
+    if let Value::Array(related) = related {
+        for (index, information) in related.iter().enumerate() {
+            if fits::<DiagnosticRelatedInformation>(information) {
+                continue;
+            }
+            let location = &information["location"];
+            let field = if fits::<Location>(location) {
+                ".message".to_owned()
+            } else if !fits::<Url>(&location["uri"]) {
+                ".location.uri".to_owned()
+            } else {
+                let range = locate_range_error(&location["range"]).unwrap_or_default();
+                format!(".location.range{range}")
+            };
+            return Some(format!(".relatedInformation[{index}]{field}"));
+        }
+    } else if !fits::<Option<Vec<DiagnosticRelatedInformation>>>(related) {
+        return Some(".relatedInformation".to_owned());
+    }
+    None
+}
+
+/// The path to the field of a range's JSON which doesn't deserialize, like `.start.line`.
+fn locate_range_error(range: &Value) -> Option<String> {
+    if fits::<Range>(range) {
+        return None;
+    }
+    for side in ["start", "end"] {
+        let position = &range[side];
+        if fits::<Position>(position) {
+            continue;
+        }
+        let field = ["line", "character"]
+            .into_iter()
+            .find(|field| !fits::<u32>(&position[*field]));
+        return Some(match field {
+            Some(field) => format!(".{side}.{field}"),
+            None => format!(".{side}"),
+        });
+    }
+    Some(String::new())
+}
+
+fn invalid_utf8(offset: u64, source: &str) -> miette::Report {
+    miette!(
+        help = "It may contain text copied from a source file which isn't UTF-8",
+        "Invalid UTF-8 at byte {offset} in diagnostics from {source}"
+    )
+}
+
+/// Whether a file is empty or only whitespace, without reading it all into memory.
+fn is_blank(path: &Path) -> bool {
+    File::open(path).is_ok_and(|file| {
+        BufReader::new(file)
+            .bytes()
+            .all(|byte| byte.is_ok_and(|byte| byte.is_ascii_whitespace()))
+    })
+}
+
+/// Strip a leading byte-order mark, converting UTF-16 to UTF-8.
+///
+/// `lua-language-server` sometimes writes a BOM on Windows.
+fn decode_bom(contents: Vec<u8>) -> miette::Result<Vec<u8>> {
+    let utf16 = |bytes: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let units = bytes
+            .chunks(2)
+            .map(|chunk| from_bytes([chunk[0], chunk.get(1).copied().unwrap_or_default()]))
+            .collect::<Vec<_>>();
+        String::from_utf16(&units)
+            .map(String::into_bytes)
+            .map_err(|err| miette!("Invalid UTF-16: {err}"))
+    };
+
+    if let Some(rest) = contents.strip_prefix(b"\xEF\xBB\xBF") {
+        Ok(rest.to_vec())
+    } else if let Some(rest) = contents.strip_prefix(b"\xFF\xFE") {
+        utf16(rest, u16::from_le_bytes)
+    } else if let Some(rest) = contents.strip_prefix(b"\xFE\xFF") {
+        utf16(rest, u16::from_be_bytes)
+    } else {
+        Ok(contents)
+    }
+}
+
+/// Deserializes the top-level object of a diagnostics file one file at a time, skipping
+/// what the [`Filter`] doesn't keep.
+struct FilteredDiagnostics<'a> {
+    filter: &'a Filter<'a>,
+    failure: &'a Failure,
+}
+
+impl<'de> DeserializeSeed<'de> for FilteredDiagnostics<'_> {
+    type Value = Diagnostics;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FilteredDiagnostics<'_> {
+    type Value = Diagnostics;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(EXPECTED_SHAPES)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut diagnostics = Diagnostics::new();
+        while let Some(uri) = map.next_key::<String>()? {
+            if !(self.filter.file)(&uri) {
+                map.next_value::<IgnoredAny>()?;
+                continue;
+            }
+            let file = map
+                .next_value_seed(FilteredFile {
+                    filter: self.filter,
+                    failure: self.failure,
+                    uri: &uri,
+                })
+                .inspect_err(|_| {
+                    *self.failure.uri.borrow_mut() = Some(uri.clone());
+                })?;
+            if !file.is_empty() {
+                diagnostics.insert(uri, file);
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    /// With `--check_out_path`, the server writes an empty array if there are no problems.
+    ///
+    /// Other tools write arrays of `{"uri", "diagnostics"}` objects, like the parameters of an
+    /// LSP `textDocument/publishDiagnostics` notification.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut diagnostics = Diagnostics::new();
+        while let Some(file) = seq.next_element::<PublishedFile>()? {
+            if !(self.filter.file)(&file.uri) {
+                continue;
+            }
+            let mut kept = Vec::new();
+            for (index, value) in file.diagnostics.into_iter().enumerate() {
+                let diagnostic = convert(self.filter, &file.uri, index, value).map_err(|err| {
+                    *self.failure.uri.borrow_mut() = Some(file.uri.clone());
+                    self.failure.index.set(Some(index));
+                    A::Error::custom(err)
+                })?;
+                kept.extend(diagnostic);
+            }
+            if !kept.is_empty() {
+                diagnostics.entry(file.uri).or_default().extend(kept);
+            }
+        }
+        Ok(diagnostics)
+    }
+}
+
+/// The shapes of diagnostics files [`FilteredDiagnostics`] accepts, for errors.
+const EXPECTED_SHAPES: &str = "an object mapping file URIs to arrays of diagnostics, like \
+    `lua-language-server --check` writes, or an array of `{\"uri\", \"diagnostics\"}` objects";
+
+/// One file's diagnostics in an array-shaped diagnostics file.
+#[derive(Deserialize)]
+#[serde(expecting = "an object with `uri` and `diagnostics` fields")]
+struct PublishedFile {
+    uri: String,
+    diagnostics: Vec<Value>,
+}
+
+/// Convert one of a file's diagnostics, or `None` if the [`Filter`] doesn't keep it or it's
+/// malformed and skipped.
+fn convert(
+    filter: &Filter<'_>,
+    uri: &str,
+    index: usize,
+    mut value: Value,
+) -> Result<Option<Diagnostic>, serde_json::Error> {
+    normalize_severity(&mut value);
+    let diagnostic = match Diagnostic::deserialize(&value) {
+        Ok(diagnostic) => diagnostic,
+        Err(err) if filter.strict => return Err(err),
+        Err(err) => {
+            let field = locate_diagnostic_error(&value).unwrap_or_default();
+            log::warn!("Skipping malformed diagnostic at `{uri:?}[{index}]{field}`: {err}");
+            return Ok(None);
+        }
+    };
+    Ok((filter.diagnostic)(&diagnostic).then_some(diagnostic))
+}
+
+/// Replace a severity given by name, like `"Warning"`, with its LSP number.
+fn normalize_severity(diagnostic: &mut Value) {
+    let Some(Value::String(name)) = diagnostic.get("severity") else {
+        return;
+    };
+    let severity = Severity::value_variants()
+        .iter()
+        .copied()
+        .find(|severity| severity.checklevel().eq_ignore_ascii_case(name))
+        .or_else(|| Severity::from_str(name, true).ok());
+    if let Some(severity) = severity {
+        if let Ok(number) = serde_json::to_value(DiagnosticSeverity::from(severity)) {
+            diagnostic["severity"] = number;
+        }
+    }
+}
+
+/// Deserializes one file's diagnostics, skipping what the [`Filter`] doesn't keep.
+struct FilteredFile<'a> {
+    filter: &'a Filter<'a>,
+    failure: &'a Failure,
+    uri: &'a str,
+}
+
+impl<'de> DeserializeSeed<'de> for FilteredFile<'_> {
+    type Value = Vec<Diagnostic>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FilteredFile<'_> {
+    type Value = Vec<Diagnostic>;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("an array of diagnostics")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut diagnostics = Vec::new();
+        for index in 0.. {
+            let record_index = |_: &A::Error| self.failure.index.set(Some(index));
+            let Some(value) = seq.next_element::<Value>().inspect_err(record_index)? else {
+                break;
+            };
+            let diagnostic = convert(self.filter, self.uri, index, value)
+                .map_err(A::Error::custom)
+                .inspect_err(record_index)?;
+            diagnostics.extend(diagnostic);
+        }
+        Ok(diagnostics)
+    }
+}
+
+/// How much to read and check at a time.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Checks that a stream is UTF-8 as it's read, so invalid bytes are reported by their offset
+/// rather than a line and column in the JSON.
+///
+/// This buffers its input, since `serde_json` reads a byte at a time.
+struct Utf8Reader<R> {
+    inner: R,
+    /// Checked bytes, read up to `pos`.
+    buf: Vec<u8>,
+    pos: usize,
+    /// How many bytes were checked before `buf`.
+    offset: u64,
+    /// The start of a character split across chunks.
+    pending: Vec<u8>,
+}
+
+impl<R> Utf8Reader<R> {
+    /// Check `inner`, which starts `offset` bytes into the stream.
+    fn new(inner: R, offset: usize) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            offset: offset as u64,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for Utf8Reader<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.buf.len() {
+            self.offset += self.buf.len() as u64;
+            self.buf.clear();
+            self.pos = 0;
+            self.buf.append(&mut self.pending);
+            let start = self.buf.len();
+            self.buf.resize(start + CHUNK_LEN, 0);
+            let n = self.inner.read(&mut self.buf[start..])?;
+            self.buf.truncate(start + n);
+            if n == 0 {
+                if self.buf.is_empty() {
+                    return Ok(0);
+                }
+                // The stream ended partway through a character.
+                return Err(InvalidUtf8 {
+                    offset: self.offset,
+                }
+                .into());
+            }
+            if let Err(err) = std::str::from_utf8(&self.buf) {
+                if err.error_len().is_some() {
+                    return Err(InvalidUtf8 {
+                        offset: self.offset + err.valid_up_to() as u64,
+                    }
+                    .into());
+                }
+                self.pending = self.buf.split_off(err.valid_up_to());
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[derive(Debug)]
+struct InvalidUtf8 {
+    offset: u64,
+}
+
+impl Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid UTF-8 at byte {}", self.offset)
+    }
+}
+
+impl std::error::Error for InvalidUtf8 {}
+
+impl From<InvalidUtf8> for std::io::Error {
+    fn from(err: InvalidUtf8) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEEP_ALL: Filter<'static> = Filter {
+        file: &|_| true,
+        diagnostic: &|_| true,
+        strict: true,
+    };
+
+    /// The `(uri, code, severity)` of each diagnostic.
+    fn summarize(diagnostics: &Diagnostics) -> Vec<(&str, String, DiagnosticSeverity)> {
+        diagnostics
+            .iter()
+            .flat_map(|(uri, file)| {
+                file.iter().map(move |diagnostic| {
+                    let code = match &diagnostic.code {
+                        Some(NumberOrString::String(code)) => code.clone(),
+                        code => format!("{code:?}"),
+                    };
+                    (uri.as_str(), code, diagnostic.severity.unwrap())
+                })
+            })
+            .collect()
+    }
+
+    /// Parse a fixture from `tests/fixtures/diagnostics` and check it has the diagnostics
+    /// every fixture there has.
+    fn check_fixture(contents: &[u8]) {
+        let diagnostics = parse(contents.to_vec(), "fixture", &KEEP_ALL, None)
+            .unwrap_or_else(|err| panic!("{err:?}"));
+        assert_eq!(
+            summarize(&diagnostics),
+            [
+                (
+                    "file:///home/user/project/init.lua",
+                    "undefined-global".to_owned(),
+                    DiagnosticSeverity::WARNING
+                ),
+                (
+                    "file:///home/user/project/init.lua",
+                    "unused-local".to_owned(),
+                    DiagnosticSeverity::HINT
+                ),
+                (
+                    "file:///home/user/project/lua/util.lua",
+                    "redundant-parameter".to_owned(),
+                    DiagnosticSeverity::WARNING
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_array_shape() {
+        // With severities by name, rather than number.
+        check_fixture(include_bytes!("../tests/fixtures/diagnostics/array.json"));
+    }
+
+    #[test]
+    fn test_object_shape() {
+        check_fixture(include_bytes!("../tests/fixtures/diagnostics/object.json"));
+    }
+
+    #[test]
+    fn test_code_description() {
+        check_fixture(include_bytes!(
+            "../tests/fixtures/diagnostics/code-description.json"
+        ));
+    }
+
+    /// A diagnostics file with a non-ASCII message.
+    const BOM_JSON: &str = r#"{"file:///init.lua": [{
+        "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 3}},
+        "severity": 2,
+        "message": "未定义的全局变量 `vim`。"
+    }]}"#;
+
+    /// [`BOM_JSON`] encoded after each byte-order mark.
+    fn encodings() -> [(&'static str, Vec<u8>); 3] {
+        let utf16 = |to_bytes: fn(u16) -> [u8; 2]| {
+            BOM_JSON
+                .encode_utf16()
+                .flat_map(to_bytes)
+                .collect::<Vec<_>>()
+        };
+        [
+            (
+                "UTF-8",
+                [b"\xEF\xBB\xBF".as_slice(), BOM_JSON.as_bytes()].concat(),
+            ),
+            (
+                "UTF-16LE",
+                [b"\xFF\xFE".to_vec(), utf16(u16::to_le_bytes)].concat(),
+            ),
+            (
+                "UTF-16BE",
+                [b"\xFE\xFF".to_vec(), utf16(u16::to_be_bytes)].concat(),
+            ),
+        ]
+    }
+
+    fn check_bom_diagnostics(diagnostics: &Diagnostics) {
+        assert_eq!(diagnostics.len(), 1);
+        let file = &diagnostics["file:///init.lua"];
+        assert_eq!(file.len(), 1);
+        assert_eq!(file[0].message, "未定义的全局变量 `vim`。");
+        assert_eq!(file[0].severity, Some(DiagnosticSeverity::WARNING));
+    }
+
+    #[test]
+    fn test_parse_bom() {
+        for (encoding, contents) in encodings() {
+            let diagnostics = parse(contents, encoding, &KEEP_ALL, None)
+                .unwrap_or_else(|err| panic!("{encoding}: {err:?}"));
+            check_bom_diagnostics(&diagnostics);
+        }
+    }
+
+    #[test]
+    fn test_read_bom() {
+        let dir = crate::TempDir::new(&std::env::temp_dir()).unwrap();
+        for (encoding, contents) in encodings() {
+            let path = dir.path().join(format!("{encoding}.json"));
+            std::fs::write(&path, contents).unwrap();
+            let diagnostics =
+                read(&path, &KEEP_ALL, None).unwrap_or_else(|err| panic!("{encoding}: {err:?}"));
+            check_bom_diagnostics(&diagnostics);
+        }
+    }
+
+    #[test]
+    fn test_unknown_shape() {
+        let err = parse(b"\"oops\"".to_vec(), "fixture", &KEEP_ALL, None).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("invalid type: string"), "{message}");
+        assert!(message.contains(EXPECTED_SHAPES), "{message}");
+    }
+
+    /// Repeats `pattern` up to `len` bytes, at most `READ_LEN` bytes a read.
+    struct Repeat {
+        pattern: &'static [u8],
+        len: usize,
+        pos: usize,
+    }
+
+    impl Read for Repeat {
+        fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+            /// Not a multiple of the pattern's length, so characters are split between reads.
+            const READ_LEN: usize = 4097;
+            let n = out.len().min(READ_LEN).min(self.len - self.pos);
+            for (index, byte) in out[..n].iter_mut().enumerate() {
+                *byte = self.pattern[(self.pos + index) % self.pattern.len()];
+            }
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_utf8_reader_streams() {
+        const PATTERN: &str = "诊断完成 ";
+        // Whole repeats, so the last character isn't cut off.
+        const LEN: usize = PATTERN.len() * 1024 * 1024;
+        let mut reader = Utf8Reader::new(
+            Repeat {
+                pattern: PATTERN.as_bytes(),
+                len: LEN,
+                pos: 0,
+            },
+            0,
+        );
+        let mut out = [0; 8 * 1024];
+        let mut total = 0;
+        loop {
+            let n = reader.read(&mut out).unwrap();
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        assert_eq!(total, LEN);
+    }
+
+    #[test]
+    fn test_utf8_reader_invalid_offset() {
+        let contents = [b"{\"\xE8\xAF\x8A".as_slice(), b"\xFF\"}"].concat();
+        let err = std::io::read_to_string(Utf8Reader::new(contents.as_slice(), 3)).unwrap_err();
+        let invalid = err
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<InvalidUtf8>()
+            .unwrap();
+        // Counting the 3-byte BOM before the reader's input.
+        assert_eq!(invalid.offset, 8);
+    }
+}
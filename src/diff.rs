@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use lsp_types::Diagnostic;
+use miette::Context;
+use miette::IntoDiagnostic;
+use owo_colors::OwoColorize;
+use owo_colors::Stream::Stdout;
+use serde::Serialize;
+
+use crate::format::JsonDiagnostic;
+use crate::write_range;
+use crate::FileDiagnostics;
+
+/// Identifies a diagnostic across runs, ignoring its position so that unrelated edits don't
+/// make it look new.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Fingerprint {
+    path: PathBuf,
+    code: Option<String>,
+    message: String,
+}
+
+impl From<&JsonDiagnostic> for Fingerprint {
+    fn from(diagnostic: &JsonDiagnostic) -> Self {
+        Self {
+            path: diagnostic.path.clone(),
+            code: diagnostic.code.clone(),
+            message: diagnostic.message.clone(),
+        }
+    }
+}
+
+/// The differences between a previous `--format json` run and the current diagnostics.
+pub struct Diff<'a> {
+    pub new: Vec<(&'a Path, &'a Diagnostic)>,
+    pub fixed: Vec<JsonDiagnostic>,
+    pub unchanged: Vec<(&'a Path, &'a Diagnostic)>,
+}
+
+impl<'a> Diff<'a> {
+    pub fn new(files: &'a [FileDiagnostics], previous: Vec<JsonDiagnostic>) -> Self {
+        // Fingerprints can repeat, so match them up by count.
+        let mut remaining = HashMap::<Fingerprint, Vec<JsonDiagnostic>>::new();
+        for diagnostic in previous {
+            remaining
+                .entry(Fingerprint::from(&diagnostic))
+                .or_default()
+                .push(diagnostic);
+        }
+
+        let mut new = Vec::new();
+        let mut unchanged = Vec::new();
+        for file in files {
+            for diagnostic in &file.diagnostics {
+                let fingerprint =
+                    Fingerprint::from(&JsonDiagnostic::new(&file.path, diagnostic, None));
+                match remaining
+                    .get_mut(&fingerprint)
+                    .and_then(|matches| matches.pop())
+                {
+                    Some(_) => unchanged.push((file.path.as_path(), diagnostic)),
+                    None => new.push((file.path.as_path(), diagnostic)),
+                }
+            }
+        }
+
+        let mut fixed = remaining.into_values().flatten().collect::<Vec<_>>();
+        fixed.sort_by(|a, b| {
+            a.path
+                .cmp(&b.path)
+                .then_with(|| a.range.start.cmp(&b.range.start))
+        });
+
+        Self {
+            new,
+            fixed,
+            unchanged,
+        }
+    }
+}
+
+/// Read a previous `--format json` run for `--diff`.
+pub fn read_previous(path: &Path) -> miette::Result<Vec<JsonDiagnostic>> {
+    let contents = std::fs::read(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read previous diagnostics: {path:?}"))?;
+    serde_json::from_slice(&contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to deserialize previous diagnostics: {path:?}"))
+}
+
+/// Write the current diagnostics as a `--fail-on-new-only` baseline, in `--format json`.
+pub fn write_baseline(path: &Path, files: &[FileDiagnostics]) -> miette::Result<()> {
+    let diagnostics = crate::format::json_diagnostics(files, None).collect::<Vec<_>>();
+    let mut contents = serde_json::to_vec_pretty(&diagnostics).into_diagnostic()?;
+    contents.push(b'\n');
+    std::fs::write(path, contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write baseline: {path:?}"))
+}
+
+/// The `--diff` report in `--format json`.
+#[derive(Debug, Serialize)]
+pub struct JsonDiff {
+    pub new: Vec<JsonDiagnostic>,
+    pub fixed: Vec<JsonDiagnostic>,
+    pub unchanged: Vec<JsonDiagnostic>,
+}
+
+/// A one-line rendering of a diagnostic, for the fixed and unchanged `--diff` sections.
+pub struct CompactDiagnostic<'a>(pub &'a JsonDiagnostic);
+
+impl<'a> Display for CompactDiagnostic<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let diagnostic = self.0;
+        write!(f, "    {}:", diagnostic.path.display())?;
+        write_range(f, diagnostic.range)?;
+        if let Some(code) = &diagnostic.code {
+            write!(
+                f,
+                " [{}]",
+                code.if_supports_color(Stdout, |text| text.bold())
+            )?;
+        }
+        if let Some(severity) = diagnostic.severity {
+            write!(f, " {severity}")?;
+        }
+        // Only show the first line of multi-line messages.
+        writeln!(
+            f,
+            ": {}",
+            diagnostic.message.lines().next().unwrap_or_default()
+        )
+    }
+}
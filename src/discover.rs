@@ -0,0 +1,331 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+use clap::parser::ValueSource;
+use miette::miette;
+
+use crate::Opts;
+
+/// File names of the server executable.
+const SERVER_NAMES: &[&str] = &["lua-language-server", "lua-language-server.exe"];
+
+/// Where the server executable may be in an install directory.
+pub fn server_executables(dir: &Path) -> Vec<PathBuf> {
+    SERVER_NAMES
+        .iter()
+        .flat_map(|name| [dir.join("bin").join(name), dir.join(name)])
+        .collect()
+}
+
+/// An error for when the server executable doesn't exist, with suggestions.
+pub fn server_not_found(opts: &Opts, program: &Path) -> miette::Report {
+    let source = match opts.lua_language_server_source {
+        Some(ValueSource::CommandLine) => "from `-c`",
+        Some(ValueSource::EnvVariable) => "from `$LUALSCHECK_SERVER`",
+        _ => "the default",
+    };
+
+    let mut candidates = Vec::new();
+    if program.components().count() > 1 {
+        for extension in [".exe", ".cmd"] {
+            let mut candidate = program.as_os_str().to_owned();
+            candidate.push(extension);
+            candidates.push(PathBuf::from(candidate));
+        }
+        if let (Some(parent), Some(name)) = (program.parent(), program.file_name()) {
+            candidates.push(parent.join("bin").join(name));
+        }
+    }
+    let help = match candidates.into_iter().find(|candidate| candidate.is_file()) {
+        Some(candidate) => format!("Did you mean `-c {}`?", candidate.display()),
+        None if opts.lua_language_server_source == Some(ValueSource::DefaultValue)
+            && !opts.no_auto_detect =>
+        {
+            "It isn't in `$PATH` or any well-known install locations; install \
+            lua-language-server (https://luals.github.io/#install) or pass its path with `-c`"
+                .to_owned()
+        }
+        None => "Install lua-language-server (https://luals.github.io/#install) and put it in \
+            `$PATH`, or pass its path with `-c`"
+            .to_owned(),
+    };
+    miette!(
+        help = help,
+        "lua-language-server executable not found: {} ({source})",
+        program.display()
+    )
+}
+
+/// Find an executable like a shell would, searching `$PATH` for bare names.
+pub fn find_executable(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(program);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if cfg!(windows) {
+            // Package managers like Mason and npm install batch file shims.
+            for extension in ["exe", "cmd", "bat"] {
+                let candidate = candidate.with_extension(extension);
+                if candidate.is_file() {
+                    return Some(candidate);
+                }
+            }
+        }
+        None
+    })
+}
+
+/// Check if a path is a Windows batch file, like a `lua-language-server.cmd` shim.
+pub fn is_batch_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("cmd") || extension.eq_ignore_ascii_case("bat")
+    })
+}
+
+/// Find the executable a batch file shim runs, like Mason's, so it can be run directly.
+///
+/// Shims run their target with `%*` to forward their arguments, and often refer to their own
+/// directory with `%~dp0`. Returns `None` for batch files which don't look like that.
+pub fn resolve_shim(shim: &Path) -> Option<PathBuf> {
+    let contents = std::fs::read_to_string(shim).ok()?;
+    let dir = shim.parent()?;
+    let line = contents.lines().rev().find(|line| line.contains("%*"))?;
+    let command = line[..line.find("%*")?]
+        .trim()
+        .trim_start_matches('@')
+        .trim_start_matches("call ")
+        .trim_start_matches("CALL ")
+        .trim();
+    let target = match command.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next()?,
+        None => command.split_whitespace().next()?,
+    };
+    let mut dir_prefix = dir.as_os_str().to_string_lossy().into_owned();
+    dir_prefix.push('\\');
+    let target = target
+        .replace("%~dp0", &dir_prefix)
+        .replace("%dp0%", &dir_prefix);
+    let target = PathBuf::from(target);
+    let is_exe = target
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"));
+    (is_exe && target.is_file()).then_some(target)
+}
+
+/// Check if a path is a file that can be run.
+pub fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// Places the server is commonly installed which may not be in `$PATH`, like Neovim's Mason
+/// or Homebrew.
+fn well_known_locations() -> Vec<PathBuf> {
+    let env_path = |name: &str| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    let mut dirs = Vec::new();
+
+    if cfg!(windows) {
+        if let Some(local_app_data) = env_path("LOCALAPPDATA") {
+            dirs.push(local_app_data.join(r"nvim-data\mason\packages\lua-language-server"));
+            dirs.push(local_app_data.join(r"Programs\lua-language-server"));
+        }
+        if let Some(scoop) =
+            env_path("SCOOP").or_else(|| env_path("USERPROFILE").map(|home| home.join("scoop")))
+        {
+            dirs.push(scoop.join(r"apps\lua-language-server\current"));
+        }
+    } else {
+        let home = env_path("HOME");
+        let data_home = env_path("XDG_DATA_HOME")
+            .or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+        if let Some(data_home) = &data_home {
+            dirs.push(data_home.join("nvim/mason/packages/lua-language-server"));
+            dirs.push(data_home.join("lua-language-server"));
+        }
+        if let Some(home) = &home {
+            dirs.push(home.join(".local"));
+            dirs.push(home.join(".local/lua-language-server"));
+        }
+        dirs.extend(
+            [
+                "/opt/homebrew",
+                "/usr/local",
+                "/home/linuxbrew/.linuxbrew",
+                "/usr/lib/lua-language-server",
+                "/opt/lua-language-server",
+            ]
+            .map(PathBuf::from),
+        );
+    }
+
+    dirs.iter()
+        .flat_map(|dir| server_executables(dir))
+        .collect()
+}
+
+/// Servers bundled with the `sumneko.lua` VS Code extension, newest first.
+///
+/// Covers VS Code, VS Code Insiders, VSCodium, and their remote servers.
+fn vscode_extension_servers() -> Vec<PathBuf> {
+    let Some(home) = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+    else {
+        return Vec::new();
+    };
+
+    let mut extensions = Vec::new();
+    for dir in [
+        ".vscode",
+        ".vscode-insiders",
+        ".vscode-oss",
+        ".vscode-server",
+        ".vscode-server-insiders",
+        ".vscodium-server",
+    ] {
+        let Ok(entries) = std::fs::read_dir(home.join(dir).join("extensions")) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Like `sumneko.lua-3.7.4` or `sumneko.lua-3.7.4-linux-x64`.
+            if let Some(version) = name.strip_prefix("sumneko.lua-") {
+                extensions.push((parse_version(version), entry.path()));
+            }
+        }
+    }
+    extensions.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    extensions
+        .into_iter()
+        .flat_map(|(_, extension)| {
+            SERVER_NAMES
+                .iter()
+                .map(move |name| extension.join("server").join("bin").join(name))
+        })
+        .collect()
+}
+
+/// Parse the leading dotted numbers of a version, like `[3, 7, 4]` for `3.7.4-linux-x64`.
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split('-')
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Check that a server runs, since extension directories can be left half-deleted by updates.
+fn runs(path: &Path) -> bool {
+    let runs = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    if !runs {
+        log::debug!("Skipping {}: `--version` failed", path.display());
+    }
+    runs
+}
+
+/// Look for the server in well-known install locations, then in VS Code extensions.
+pub fn auto_detect() -> Option<PathBuf> {
+    let found = well_known_locations()
+        .into_iter()
+        .find(|candidate| is_executable(candidate))
+        .or_else(|| {
+            vscode_extension_servers()
+                .into_iter()
+                .find(|candidate| is_executable(candidate) && runs(candidate))
+        });
+    match &found {
+        Some(path) => log::info!("Auto-detected lua-language-server: {}", path.display()),
+        None => log::debug!("Didn't find lua-language-server in any well-known locations"),
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_batch_file() {
+        assert!(is_batch_file(Path::new("lua-language-server.cmd")));
+        assert!(is_batch_file(Path::new(
+            r"C:\Program Files\lua\LUA-LANGUAGE-SERVER.BAT"
+        )));
+        assert!(!is_batch_file(Path::new("lua-language-server.exe")));
+        assert!(!is_batch_file(Path::new("lua-language-server")));
+    }
+
+    /// A directory under one with a space in its name, like `C:\Program Files`.
+    #[cfg(windows)]
+    fn dir_with_spaces(temp: &crate::TempDir) -> PathBuf {
+        let dir = temp.path().join("Program Files").join("lua");
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        dir
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_resolve_shim() {
+        let temp = crate::TempDir::new(&std::env::temp_dir()).unwrap();
+        let dir = dir_with_spaces(&temp);
+        let exe = dir.join("bin").join("lua-language-server.exe");
+        std::fs::write(&exe, "").unwrap();
+
+        for contents in [
+            "@ECHO off\r\n\"%~dp0\\bin\\lua-language-server.exe\" %*\r\n",
+            "@ECHO off\r\nSET dp0=%~dp0\r\n\"%dp0%\\bin\\lua-language-server.exe\" %*\r\n",
+            "@call \"%~dp0bin\\lua-language-server.exe\" %*\r\n",
+        ] {
+            let shim = dir.join("lua-language-server.cmd");
+            std::fs::write(&shim, contents).unwrap();
+            assert_eq!(resolve_shim(&shim), Some(exe.clone()), "{contents:?}");
+        }
+
+        let shim = dir.join("other.cmd");
+        std::fs::write(&shim, "@ECHO off\r\nnode \"%~dp0\\server.js\" %*\r\n").unwrap();
+        assert_eq!(resolve_shim(&shim), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_batch_file_arguments_with_spaces() {
+        let temp = crate::TempDir::new(&std::env::temp_dir()).unwrap();
+        let shim = dir_with_spaces(&temp).join("lua-language-server.cmd");
+        std::fs::write(&shim, "@ECHO off\r\nECHO %~1\r\n").unwrap();
+        assert_eq!(find_executable(&shim), Some(shim.clone()));
+
+        let argument = r"C:\Program Files\project\init.lua";
+        let output = Command::new(&shim).arg(argument).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), argument);
+    }
+}
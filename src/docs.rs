@@ -0,0 +1,293 @@
+/// The lua-language-server wiki page describing each diagnostic.
+const DIAGNOSTICS_URL: &str = "https://luals.github.io/wiki/diagnostics/";
+
+/// Diagnostic codes with their own section on the wiki page, anchored by the code itself.
+///
+/// Other codes, like syntax errors, link to the top of the page.
+const DOCUMENTED_CODES: &[&str] = &[
+    "ambiguity-1",
+    "assign-type-mismatch",
+    "await-in-sync",
+    "cast-local-type",
+    "cast-type-mismatch",
+    "circle-doc-class",
+    "close-non-object",
+    "code-after-break",
+    "codestyle-check",
+    "count-down-loop",
+    "deprecated",
+    "different-requires",
+    "discard-returns",
+    "doc-field-no-class",
+    "duplicate-doc-alias",
+    "duplicate-doc-field",
+    "duplicate-doc-param",
+    "duplicate-index",
+    "duplicate-set-field",
+    "empty-block",
+    "global-in-nil-env",
+    "incomplete-signature-doc",
+    "inject-field",
+    "invisible",
+    "lowercase-global",
+    "missing-fields",
+    "missing-global-doc",
+    "missing-local-export-doc",
+    "missing-parameter",
+    "missing-return",
+    "missing-return-value",
+    "name-style-check",
+    "need-check-nil",
+    "newfield-call",
+    "newline-call",
+    "no-unknown",
+    "not-yieldable",
+    "param-type-mismatch",
+    "redefined-local",
+    "redundant-parameter",
+    "redundant-return",
+    "redundant-return-value",
+    "redundant-value",
+    "return-type-mismatch",
+    "spell-check",
+    "trailing-space",
+    "unbalanced-assignments",
+    "undefined-doc-class",
+    "undefined-doc-name",
+    "undefined-doc-param",
+    "undefined-env-child",
+    "undefined-field",
+    "undefined-global",
+    "unknown-cast-variable",
+    "unknown-diag-code",
+    "unknown-operator",
+    "unreachable-code",
+    "unused-function",
+    "unused-label",
+    "unused-local",
+    "unused-vararg",
+];
+
+/// A link to the documentation for a diagnostic code, for `--show-rule-docs`.
+pub fn rule_docs_url(code: &str) -> String {
+    if DOCUMENTED_CODES.binary_search(&code).is_ok() {
+        format!("{DIAGNOSTICS_URL}#{code}")
+    } else {
+        DIAGNOSTICS_URL.to_owned()
+    }
+}
+
+/// What a diagnostic code means and how to fix it, for `lualscheck explain`.
+#[derive(Debug)]
+struct Explanation {
+    code: &'static str,
+    /// What triggers the diagnostic.
+    description: &'static str,
+    fix: &'static str,
+}
+
+/// Explanations of common diagnostic codes, sorted by code.
+const EXPLANATIONS: &[Explanation] = &[
+    Explanation {
+        code: "ambiguity-1",
+        description: "An expression's operator precedence is easy to misread, like `not a == b`, which means `(not a) == b`.",
+        fix: "Add parentheses to make the intended order explicit.",
+    },
+    Explanation {
+        code: "assign-type-mismatch",
+        description: "A value is assigned to a variable or field whose declared type it doesn't match.",
+        fix: "Assign a value of the declared type, or widen the declaration, like `---@type string|nil`.",
+    },
+    Explanation {
+        code: "cast-local-type",
+        description: "A local variable is assigned a value of a different type than the one it was declared or first assigned with.",
+        fix: "Use a separate variable, or declare the local with every type it holds, like `---@type integer|string`.",
+    },
+    Explanation {
+        code: "code-after-break",
+        description: "Code follows a `break` in the same block, so it never runs.",
+        fix: "Remove the code, or move it before the `break`.",
+    },
+    Explanation {
+        code: "deprecated",
+        description: "Code uses something marked `---@deprecated`, or a standard library function deprecated in the configured Lua version.",
+        fix: "Switch to the replacement named in the deprecation message, or set `runtime.version` in `.luarc.json` if the wrong Lua version is configured.",
+    },
+    Explanation {
+        code: "discard-returns",
+        description: "The result of a function marked `---@nodiscard` is ignored.",
+        fix: "Use the returned value, or assign it to `_` if ignoring it is intended.",
+    },
+    Explanation {
+        code: "duplicate-doc-field",
+        description: "A class declares the same `---@field` more than once.",
+        fix: "Remove or rename the duplicate field.",
+    },
+    Explanation {
+        code: "duplicate-index",
+        description: "A table constructor sets the same key more than once, so only the last value is kept.",
+        fix: "Remove or rename the duplicate key.",
+    },
+    Explanation {
+        code: "duplicate-set-field",
+        description: "A field, usually a method, is defined more than once on the same table.",
+        fix: "Remove the duplicate definition, or rename one of them.",
+    },
+    Explanation {
+        code: "empty-block",
+        description: "A block, like the body of an `if` or a loop, is empty.",
+        fix: "Remove the block, or add the missing code or a comment explaining why it's empty.",
+    },
+    Explanation {
+        code: "inject-field",
+        description: "A field is assigned on a value of a class type which doesn't declare it.",
+        fix: "Declare the field on the class with `---@field`, or fix the field name.",
+    },
+    Explanation {
+        code: "lowercase-global",
+        description: "A global variable whose name starts with a lowercase letter is assigned, which is usually a missing `local`.",
+        fix: "Add `local`, or list the name in `diagnostics.globals` in `.luarc.json` if it's meant to be global.",
+    },
+    Explanation {
+        code: "missing-fields",
+        description: "A table constructor for a class type is missing fields the class declares as required.",
+        fix: "Add the missing fields, or mark them optional on the class, like `---@field name? string`.",
+    },
+    Explanation {
+        code: "missing-parameter",
+        description: "A function is called with fewer arguments than it has required parameters.",
+        fix: "Pass the missing arguments, or mark the parameters optional, like `---@param name? string`.",
+    },
+    Explanation {
+        code: "missing-return",
+        description: "A function annotated with `---@return` can reach its end without returning.",
+        fix: "Return a value on every path, or mark the return value optional, like `---@return string?`.",
+    },
+    Explanation {
+        code: "missing-return-value",
+        description: "A `return` statement returns fewer values than the function's `---@return` annotations declare.",
+        fix: "Return every declared value, or mark the missing ones optional.",
+    },
+    Explanation {
+        code: "need-check-nil",
+        description: "A value which may be `nil` is indexed or called without checking it first.",
+        fix: "Check that it isn't `nil` first, for example with `if value then` or `assert(value)`.",
+    },
+    Explanation {
+        code: "newline-call",
+        description: "A line starts with `(`, which Lua parses as a call of the previous line's expression.",
+        fix: "End the previous statement with `;`, or join the lines if the call is intended.",
+    },
+    Explanation {
+        code: "param-type-mismatch",
+        description: "An argument doesn't match the type declared for the parameter with `---@param`.",
+        fix: "Pass a value of the declared type, or widen the parameter's type.",
+    },
+    Explanation {
+        code: "redefined-local",
+        description: "A local variable shadows another local with the same name which is still in scope.",
+        fix: "Rename one of the variables, or assign to the existing one instead of declaring a new one.",
+    },
+    Explanation {
+        code: "redundant-parameter",
+        description: "A function is called with more arguments than it has parameters.",
+        fix: "Remove the extra arguments, or add the parameters to the function, or `...` if it takes any number.",
+    },
+    Explanation {
+        code: "redundant-return-value",
+        description: "A `return` statement returns more values than the function's `---@return` annotations declare.",
+        fix: "Remove the extra values, or declare them with more `---@return` annotations.",
+    },
+    Explanation {
+        code: "redundant-value",
+        description: "An assignment has more values than variables, so the extra values are discarded.",
+        fix: "Remove the extra values, or add variables for them.",
+    },
+    Explanation {
+        code: "return-type-mismatch",
+        description: "A returned value doesn't match the type declared with `---@return`.",
+        fix: "Return a value of the declared type, or widen the declared return type.",
+    },
+    Explanation {
+        code: "trailing-space",
+        description: "A line ends with spaces or tabs.",
+        fix: "Remove the trailing whitespace.",
+    },
+    Explanation {
+        code: "unbalanced-assignments",
+        description: "An assignment has fewer values than variables, so the extra variables are set to `nil`.",
+        fix: "Add values for every variable, or assign `nil` explicitly.",
+    },
+    Explanation {
+        code: "undefined-doc-name",
+        description: "An annotation refers to a type which isn't defined.",
+        fix: "Define the type with `---@class` or `---@alias`, fix its name, or add the library it comes from to `workspace.library` in `.luarc.json`.",
+    },
+    Explanation {
+        code: "undefined-doc-param",
+        description: "A `---@param` annotation names a parameter the function doesn't have.",
+        fix: "Fix the parameter's name in the annotation, or remove the annotation.",
+    },
+    Explanation {
+        code: "undefined-field",
+        description: "A field is read from a value whose type doesn't declare it.",
+        fix: "Fix the field name, declare the field with `---@field`, or check the value's type annotations.",
+    },
+    Explanation {
+        code: "undefined-global",
+        description: "A global variable is read but never defined in the project or its libraries.",
+        fix: "Fix the name, define the global, list it in `diagnostics.globals` in `.luarc.json`, or add the library defining it to `workspace.library`.",
+    },
+    Explanation {
+        code: "unreachable-code",
+        description: "Code can never run, like code after an unconditional `return` or `error`.",
+        fix: "Remove the code, or fix the control flow which skips it.",
+    },
+    Explanation {
+        code: "unused-function",
+        description: "A local function is defined but never called.",
+        fix: "Remove the function, or use it.",
+    },
+    Explanation {
+        code: "unused-label",
+        description: "A `::label::` is defined but no `goto` jumps to it.",
+        fix: "Remove the label.",
+    },
+    Explanation {
+        code: "unused-local",
+        description: "A local variable or parameter is defined but never used.",
+        fix: "Remove it, or rename it to `_` if it's a placeholder, like an ignored loop variable.",
+    },
+    Explanation {
+        code: "unused-vararg",
+        description: "A function takes `...` but never uses it.",
+        fix: "Remove the `...` parameter.",
+    },
+];
+
+/// Describe a diagnostic code for `lualscheck explain`: what triggers it, how to fix or
+/// suppress it, and a link to its documentation, wrapped to `width`.
+pub fn explain(code: &str, width: usize) -> String {
+    let url = rule_docs_url(code);
+    let Ok(index) = EXPLANATIONS.binary_search_by_key(&code, |explanation| explanation.code) else {
+        return format!("No description of `{code}` is available; see {url}\n");
+    };
+    let explanation = &EXPLANATIONS[index];
+    let paragraphs = [
+        code.to_owned(),
+        explanation.description.to_owned(),
+        format!("To fix: {}", explanation.fix),
+        format!(
+            "To suppress: add `---@diagnostic disable-next-line: {code}` above the line, list \
+            it in `diagnostics.disable` in `.luarc.json`, or pass `--exclude-code {code}`."
+        ),
+        format!("Documentation: {url}"),
+    ];
+    let mut explained = paragraphs
+        .iter()
+        .map(|paragraph| textwrap::fill(paragraph, width))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    explained.push('\n');
+    explained
+}
@@ -0,0 +1,433 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use lsp_types::Diagnostic;
+use lsp_types::Range;
+use lsp_types::Url;
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::code_to_string;
+use crate::link_url;
+use crate::version::Version;
+use crate::FileDiagnostics;
+use crate::Severity;
+use crate::SeverityCounts;
+
+/// A diagnostic in the `--format json` output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonDiagnostic {
+    /// The file's path, relative to the project root.
+    pub path: PathBuf,
+    /// Zero-indexed, like LSP ranges.
+    pub range: Range,
+    pub severity: Option<Severity>,
+    pub code: Option<String>,
+    pub message: String,
+    /// A link to the diagnostic's location, if `--link-base` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+}
+
+impl JsonDiagnostic {
+    pub fn new(path: &Path, diagnostic: &Diagnostic, link_base: Option<&Url>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            range: diagnostic.range,
+            severity: diagnostic.severity.and_then(Severity::from_lsp),
+            code: diagnostic.code.as_ref().map(code_to_string),
+            message: diagnostic.message.clone(),
+            url: link_base.map(|base| link_url(base, path, diagnostic.range.start.line)),
+        }
+    }
+}
+
+/// Run metadata for `--summary-json`.
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    /// The number of diagnostics displayed.
+    pub diagnostics: usize,
+    /// The number of diagnostics counted towards failing the run.
+    pub failing: usize,
+    /// The `--fail` threshold.
+    pub fail: Severity,
+    pub severities: SeverityCounts,
+    /// The output of `lua-language-server --version`, if it succeeded.
+    pub server_version: Option<String>,
+    /// The version parsed from `server_version`, like `3.7.4`.
+    pub luals_version: Option<Version>,
+    pub elapsed_seconds: f64,
+    pub server_seconds: f64,
+    /// The error the run failed with, apart from finding problems.
+    pub error: Option<String>,
+}
+
+/// Collects the report in memory and writes it to stdout in one go when flushed, so it isn't
+/// interleaved with other output and stdout is only locked once.
+#[derive(Debug, Default)]
+pub struct BufferedStdout(Vec<u8>);
+
+impl Write for BufferedStdout {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&self.0)?;
+        stdout.flush()?;
+        self.0.clear();
+        Ok(())
+    }
+}
+
+pub fn json_diagnostics<'a>(
+    files: &'a [FileDiagnostics],
+    link_base: Option<&'a Url>,
+) -> impl Iterator<Item = JsonDiagnostic> + 'a {
+    files.iter().flat_map(move |file| {
+        file.diagnostics
+            .iter()
+            .map(move |diagnostic| JsonDiagnostic::new(&file.path, diagnostic, link_base))
+    })
+}
+
+pub fn write_json(out: &mut dyn Write, value: &impl Serialize) -> miette::Result<()> {
+    serde_json::to_writer_pretty(&mut *out, value).into_diagnostic()?;
+    writeln!(out).into_diagnostic()
+}
+
+/// Write diagnostics as newline-delimited JSON objects, flushing after each one.
+pub fn write_ndjson(
+    out: &mut dyn Write,
+    files: &[FileDiagnostics],
+    link_base: Option<&Url>,
+) -> miette::Result<()> {
+    for diagnostic in json_diagnostics(files, link_base) {
+        serde_json::to_writer(&mut *out, &diagnostic).into_diagnostic()?;
+        writeln!(out).into_diagnostic()?;
+        out.flush().into_diagnostic()?;
+    }
+    Ok(())
+}
+
+/// Write diagnostics as CSV with a header row, using one-indexed positions.
+pub fn write_csv(out: &mut dyn Write, files: &[FileDiagnostics]) -> miette::Result<()> {
+    writeln!(
+        out,
+        "path,line,column,end_line,end_column,severity,code,message"
+    )
+    .into_diagnostic()?;
+    for diagnostic in json_diagnostics(files, None) {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            csv_escape(&diagnostic.path.to_string_lossy()),
+            diagnostic.range.start.line + 1,
+            diagnostic.range.start.character + 1,
+            diagnostic.range.end.line + 1,
+            diagnostic.range.end.character + 1,
+            diagnostic
+                .severity
+                .map(|severity| severity.to_string())
+                .unwrap_or_default(),
+            csv_escape(diagnostic.code.as_deref().unwrap_or_default()),
+            csv_escape(&diagnostic.message),
+        )
+        .into_diagnostic()?;
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if it contains commas, quotes, or newlines.
+fn csv_escape(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Write diagnostics as TeamCity `inspectionType` and `inspection` service messages.
+///
+/// See: <https://www.jetbrains.com/help/teamcity/service-messages.html#Reporting+Inspections>
+pub fn write_teamcity(out: &mut dyn Write, files: &[FileDiagnostics]) -> miette::Result<()> {
+    let codes = files
+        .iter()
+        .flat_map(|file| &file.diagnostics)
+        .map(|diagnostic| teamcity_type_id(diagnostic.code.as_ref()))
+        .collect::<BTreeSet<_>>();
+    for code in &codes {
+        let code = teamcity_escape(code);
+        writeln!(
+            out,
+            "##teamcity[inspectionType id='{code}' name='{code}' description='{code}' category='lua-language-server']"
+        )
+        .into_diagnostic()?;
+    }
+
+    for file in files {
+        let path = teamcity_escape(&file.path.to_string_lossy());
+        for diagnostic in &file.diagnostics {
+            let severity = match diagnostic.severity.and_then(Severity::from_lsp) {
+                Some(Severity::Error) => "ERROR",
+                Some(Severity::Warning) | None => "WARNING",
+                Some(Severity::Information) => "INFO",
+                Some(Severity::Hint) => "WEAK WARNING",
+            };
+            writeln!(
+                out,
+                "##teamcity[inspection typeId='{}' message='{}' file='{path}' line='{}' SEVERITY='{severity}']",
+                teamcity_escape(&teamcity_type_id(diagnostic.code.as_ref())),
+                teamcity_escape(&diagnostic.message),
+                diagnostic.range.start.line + 1,
+            )
+            .into_diagnostic()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write diagnostics as `path:line:col: severity: message` lines, which Emacs's
+/// `compilation-mode` recognizes.
+///
+/// Messages are joined onto one line so every line is a diagnostic.
+pub fn write_emacs(out: &mut dyn Write, files: &[FileDiagnostics]) -> miette::Result<()> {
+    for diagnostic in json_diagnostics(files, None) {
+        let severity = match diagnostic.severity {
+            Some(Severity::Error) => "error",
+            Some(Severity::Warning) | None => "warning",
+            Some(Severity::Information) => "info",
+            Some(Severity::Hint) => "hint",
+        };
+        writeln!(
+            out,
+            "{}:{}:{}: {severity}: {}",
+            diagnostic.path.display(),
+            diagnostic.range.start.line + 1,
+            diagnostic.range.start.character + 1,
+            diagnostic.message.lines().collect::<Vec<_>>().join(" "),
+        )
+        .into_diagnostic()?;
+    }
+    Ok(())
+}
+
+/// Write diagnostics as Checkstyle XML, with one `<file>` element per file.
+pub fn write_checkstyle(out: &mut dyn Write, files: &[FileDiagnostics]) -> miette::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).into_diagnostic()?;
+    writeln!(out, r#"<checkstyle version="4.3">"#).into_diagnostic()?;
+    for file in files {
+        writeln!(
+            out,
+            r#"  <file name="{}">"#,
+            xml_escape(&file.path.to_string_lossy())
+        )
+        .into_diagnostic()?;
+        for diagnostic in &file.diagnostics {
+            let severity = match diagnostic.severity.and_then(Severity::from_lsp) {
+                Some(Severity::Error) => "error",
+                Some(Severity::Warning) | None => "warning",
+                Some(Severity::Information) | Some(Severity::Hint) => "info",
+            };
+            write!(
+                out,
+                r#"    <error line="{}" column="{}" severity="{severity}" message="{}""#,
+                diagnostic.range.start.line + 1,
+                diagnostic.range.start.character + 1,
+                xml_escape(&diagnostic.message),
+            )
+            .into_diagnostic()?;
+            if let Some(code) = &diagnostic.code {
+                write!(out, r#" source="{}""#, xml_escape(&code_to_string(code)))
+                    .into_diagnostic()?;
+            }
+            writeln!(out, "/>").into_diagnostic()?;
+        }
+        writeln!(out, "  </file>").into_diagnostic()?;
+    }
+    writeln!(out, "</checkstyle>").into_diagnostic()
+}
+
+/// Escape a value in an XML attribute.
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' => escaped.push_str("&#9;"),
+            // Other control characters aren't allowed in XML 1.0.
+            c if c.is_control() => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn teamcity_type_id(code: Option<&lsp_types::NumberOrString>) -> String {
+    code.map(code_to_string)
+        .unwrap_or_else(|| "lua-language-server".to_owned())
+}
+
+/// Escape a value in a TeamCity service message.
+fn teamcity_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '|' => escaped.push_str("||"),
+            '\'' => escaped.push_str("|'"),
+            '[' => escaped.push_str("|["),
+            ']' => escaped.push_str("|]"),
+            '\n' => escaped.push_str("|n"),
+            '\r' => escaped.push_str("|r"),
+            '\u{0085}' => escaped.push_str("|x"),
+            '\u{2028}' => escaped.push_str("|l"),
+            '\u{2029}' => escaped.push_str("|p"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::DiagnosticSeverity;
+    use lsp_types::NumberOrString;
+    use lsp_types::Position;
+
+    use super::*;
+
+    fn file(path: &str, diagnostics: Vec<Diagnostic>) -> FileDiagnostics {
+        FileDiagnostics {
+            path: PathBuf::from(path),
+            diagnostics,
+        }
+    }
+
+    fn diagnostic(start: (u32, u32), end: (u32, u32), code: &str, message: &str) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(code.to_owned())),
+            message: message.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn render(write: fn(&mut dyn Write, &[FileDiagnostics]) -> miette::Result<()>) -> String {
+        let files = [file(
+            "lua/a, b.lua",
+            vec![
+                diagnostic(
+                    (0, 0),
+                    (0, 3),
+                    "undefined-global",
+                    "Undefined global `vim`.",
+                ),
+                diagnostic(
+                    (4, 10),
+                    (5, 2),
+                    "param-type-mismatch",
+                    "Cannot assign `\"a\"` to `integer`,\nor to `nil`.",
+                ),
+            ],
+        )];
+        let mut out = Vec::new();
+        write(&mut out, &files).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert!(matches!(csv_escape("plain"), Cow::Borrowed(_)));
+        assert_eq!(csv_escape("a, b"), "\"a, b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("one\ntwo"), "\"one\ntwo\"");
+        assert_eq!(csv_escape("one\r\ntwo"), "\"one\r\ntwo\"");
+    }
+
+    #[test]
+    fn test_write_csv() {
+        assert_eq!(
+            render(write_csv),
+            "path,line,column,end_line,end_column,severity,code,message\n\
+            \"lua/a, b.lua\",1,1,1,4,warning,undefined-global,Undefined global `vim`.\n\
+            \"lua/a, b.lua\",5,11,6,3,warning,param-type-mismatch,\
+            \"Cannot assign `\"\"a\"\"` to `integer`,\nor to `nil`.\"\n"
+        );
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(
+            xml_escape("<a href=\"x\">Tom & Jerry's</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(xml_escape("one\ntwo\r\tthree"), "one&#10;two&#13;&#9;three");
+        // Not allowed in XML 1.0, even escaped.
+        assert_eq!(xml_escape("bell\u{7}nul\0"), "bell\u{FFFD}nul\u{FFFD}");
+        assert_eq!(xml_escape("诊断"), "诊断");
+    }
+
+    #[test]
+    fn test_write_checkstyle() {
+        assert_eq!(
+            render(write_checkstyle),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<checkstyle version="4.3">
+  <file name="lua/a, b.lua">
+    <error line="1" column="1" severity="warning" message="Undefined global `vim`." source="undefined-global"/>
+    <error line="5" column="11" severity="warning" message="Cannot assign `&quot;a&quot;` to `integer`,&#10;or to `nil`." source="param-type-mismatch"/>
+  </file>
+</checkstyle>
+"#
+        );
+    }
+
+    #[test]
+    fn test_write_checkstyle_source() {
+        let mut without_code = diagnostic((0, 0), (0, 1), "", "No code & no source.");
+        without_code.code = None;
+        let mut numeric_code = diagnostic((1, 0), (1, 1), "", "A numeric code.");
+        numeric_code.code = Some(NumberOrString::Number(1001));
+        let files = [file(
+            "init.lua",
+            vec![
+                without_code,
+                numeric_code,
+                diagnostic((2, 0), (2, 1), "a<b>&\"c\"", "An odd code."),
+            ],
+        )];
+        let mut out = Vec::new();
+        write_checkstyle(&mut out, &files).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let errors = out
+            .lines()
+            .filter(|line| line.trim_start().starts_with("<error"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                r#"    <error line="1" column="1" severity="warning" message="No code &amp; no source."/>"#,
+                r#"    <error line="2" column="1" severity="warning" message="A numeric code." source="1001"/>"#,
+                r#"    <error line="3" column="1" severity="warning" message="An odd code." source="a&lt;b&gt;&amp;&quot;c&quot;"/>"#,
+            ]
+        );
+    }
+}
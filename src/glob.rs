@@ -0,0 +1,203 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use miette::miette;
+use miette::Context;
+use miette::IntoDiagnostic;
+use regex::Regex;
+
+use crate::strip_path_prefix;
+
+/// A glob pattern matched against `/`-separated relative paths.
+///
+/// - `*` matches any characters except `/`.
+/// - `**` matches any characters, including `/`.
+/// - `?` matches any single character except `/`.
+/// - `[abc]` and `[!abc]` match character classes, with ranges like `[a-z]`.
+///
+/// Patterns without a `/` match at any depth, and a pattern matching a directory matches
+/// everything under it.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> miette::Result<Self> {
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = trimmed.trim_start_matches('/');
+        let mut regex = String::from("^");
+        if !trimmed.contains('/') {
+            regex.push_str("(?:.*/)?");
+        }
+
+        let mut chars = anchored.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => {
+                    regex.push('[');
+                    if matches!(chars.peek(), Some('!' | '^')) {
+                        chars.next();
+                        regex.push('^');
+                    }
+                    // Only ranges are special in a class; everything else, including what
+                    // the regex syntax gives meaning to, like `\d` and `&&`, is literal.
+                    let mut first = true;
+                    loop {
+                        match chars.next() {
+                            // A `]` first in the class is literal, like in shell globs.
+                            Some(']') if !first => break,
+                            Some('-') => regex.push('-'),
+                            Some(c) if c.is_ascii_punctuation() => {
+                                regex.push('\\');
+                                regex.push(c);
+                            }
+                            Some(c) => regex.push(c),
+                            None => {
+                                return Err(miette!("Unclosed `[` in glob pattern: {pattern:?}"))
+                            }
+                        }
+                        first = false;
+                    }
+                    regex.push(']');
+                }
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        // Match everything under a matching directory.
+        regex.push_str("(?:/.*)?$");
+
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            regex: Regex::new(&regex)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid glob pattern: {pattern:?}"))?,
+        })
+    }
+
+    /// Check if a relative path matches this pattern.
+    pub fn is_match(&self, path: &Path) -> bool {
+        let path = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(component) => Some(component.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        self.regex.is_match(&path)
+    }
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+/// A glob pattern for paths relative to a base directory.
+#[derive(Debug, Clone)]
+pub struct Exclude {
+    /// The absolute directory the pattern is relative to.
+    pub base: PathBuf,
+    pub glob: Glob,
+}
+
+impl Exclude {
+    /// Check if an absolute path matches this pattern.
+    pub fn is_match(&self, path: &Path) -> bool {
+        strip_path_prefix(path, &self.base).is_some_and(|relative| self.glob.is_match(&relative))
+    }
+}
+
+/// Read newline-delimited glob patterns from a file, skipping blank lines and `#` comments.
+///
+/// Patterns are relative to the file's directory.
+pub fn read_ignore_file(path: &Path) -> miette::Result<Vec<Exclude>> {
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read ignore file: {path:?}"))?;
+    let base = path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("/"));
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            Ok(Exclude {
+                base: base.clone(),
+                glob: Glob::new(line).wrap_err_with(|| format!("In ignore file {path:?}"))?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Glob::new(pattern).unwrap().is_match(Path::new(path))
+    }
+
+    #[test]
+    fn test_class() {
+        assert!(matches("[ab].lua", "a.lua"));
+        assert!(!matches("[ab].lua", "c.lua"));
+        assert!(matches("[a-c].lua", "b.lua"));
+        assert!(matches("[!a-c].lua", "d.lua"));
+        assert!(!matches("[^a-c].lua", "b.lua"));
+        assert!(matches("[]].lua", "].lua"));
+    }
+
+    #[test]
+    fn test_class_regex_syntax_is_literal() {
+        assert!(matches("[\\d].lua", "d.lua"));
+        assert!(!matches("[\\d].lua", "1.lua"));
+        assert!(matches("[a&&b].lua", "&.lua"));
+        assert!(!matches("[[:alpha:]].lua", "x.lua"));
+        assert!(matches("[[:alpha:]].lua", ":].lua"));
+        assert!(matches("[.].lua", "..lua"));
+        assert!(!matches("[.].lua", "x.lua"));
+    }
+
+    #[test]
+    fn test_exclude() {
+        let exclude = Exclude {
+            base: PathBuf::from("/home/jane/project"),
+            glob: Glob::new("lua/*.lua").unwrap(),
+        };
+        assert!(exclude.is_match(Path::new("/home/jane/project/lua/init.lua")));
+        assert!(!exclude.is_match(Path::new("/home/jane/project/init.lua")));
+        assert!(!exclude.is_match(Path::new("/home/jane/other/lua/init.lua")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_exclude_drive_letter_case() {
+        let exclude = Exclude {
+            base: PathBuf::from(r"C:\Users\Jane\project"),
+            glob: Glob::new("lua/*.lua").unwrap(),
+        };
+        assert!(exclude.is_match(Path::new(r"c:\Users\Jane\project\lua\init.lua")));
+        assert!(exclude.is_match(Path::new("C:/Users/Jane/project/lua/init.lua")));
+        assert!(!exclude.is_match(Path::new(r"c:\Users\Jane\project\init.lua")));
+    }
+}
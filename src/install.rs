@@ -0,0 +1,341 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+use miette::miette;
+use miette::Context;
+use miette::IntoDiagnostic;
+
+use crate::cache_dir;
+use crate::discover;
+use crate::usage_error;
+use crate::Opts;
+
+/// The `lua-language-server` release installed by default.
+pub const DEFAULT_VERSION: &str = "3.7.4";
+
+/// SHA-256 digests of the [`DEFAULT_VERSION`] release archives, by platform, from the release's
+/// page on GitHub.
+///
+/// Installing on a platform missing here, or another version, needs `--luals-sha256`.
+const DEFAULT_VERSION_SHA256: &[(&str, &str)] = &[];
+
+const RELEASES_URL: &str = "https://github.com/LuaLS/lua-language-server/releases/download";
+
+/// Download a `lua-language-server` release for this platform into the cache directory,
+/// returning the path to its executable.
+///
+/// Releases which are already installed aren't downloaded again. Downloads are extracted into
+/// a temporary directory which is only moved into place once it's complete, so a failed
+/// download is never picked up by later runs.
+pub fn install(opts: &Opts) -> miette::Result<PathBuf> {
+    let version = &opts.luals_version;
+    let platform = platform()?;
+    let (asset, is_zip) = asset_name(version, platform);
+    let versions_dir = cache_dir()
+        .ok_or_else(|| {
+            miette!("Couldn't find a cache directory to install lua-language-server into")
+        })?
+        .join("lualscheck")
+        .join("lua-language-server");
+    let install_dir = versions_dir.join(version);
+
+    if let Some(executable) = installed_executable(&install_dir) {
+        log::info!(
+            "lua-language-server {version} is already installed: {}",
+            executable.display()
+        );
+        return Ok(executable);
+    }
+
+    let expected_sha256 = expected_sha256(opts, platform)?;
+
+    std::fs::create_dir_all(&versions_dir)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create directory: {}", versions_dir.display()))?;
+    let temp = TempDir(versions_dir.join(format!(".{version}.{}.tmp", std::process::id())));
+    let extract_dir = temp.0.join("extract");
+    std::fs::create_dir_all(&extract_dir)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create directory: {}", extract_dir.display()))?;
+
+    let url = format!("{RELEASES_URL}/{version}/{asset}");
+    let archive = temp.0.join(&asset);
+    eprintln!("Downloading {url}");
+    run(
+        Command::new("curl")
+            .args([
+                "--fail",
+                "--location",
+                "--silent",
+                "--show-error",
+                "--retry",
+                "2",
+            ])
+            .arg("--output")
+            .arg(&archive)
+            .arg(&url),
+        "download lua-language-server with `curl`",
+    )?;
+
+    let contents = std::fs::read(&archive)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read download: {}", archive.display()))?;
+    let actual = sha256_hex(&contents);
+    if !actual.eq_ignore_ascii_case(expected_sha256) {
+        return Err(miette!(
+            "Checksum mismatch for {url}\nExpected SHA-256: {expected_sha256}\nActual SHA-256:   {actual}"
+        ));
+    }
+
+    let mut tar = Command::new("tar");
+    tar.arg(if is_zip { "-xf" } else { "-xzf" })
+        .arg(&archive)
+        .arg("-C")
+        .arg(&extract_dir);
+    run(&mut tar, "extract lua-language-server with `tar`")?;
+
+    if installed_executable(&extract_dir).is_none() {
+        return Err(miette!(
+            "Release archive {asset} doesn't contain a lua-language-server executable"
+        ));
+    }
+    match std::fs::rename(&extract_dir, &install_dir) {
+        Ok(()) => {}
+        // Another run may have installed it at the same time.
+        Err(_) if installed_executable(&install_dir).is_some() => {}
+        Err(err) => {
+            return Err(err).into_diagnostic().wrap_err_with(|| {
+                format!(
+                    "Failed to move lua-language-server into {}",
+                    install_dir.display()
+                )
+            })
+        }
+    }
+
+    let executable = installed_executable(&install_dir).ok_or_else(|| {
+        miette!(
+            "Failed to install lua-language-server into {}",
+            install_dir.display()
+        )
+    })?;
+    eprintln!(
+        "Installed lua-language-server {version} to {}",
+        install_dir.display()
+    );
+    Ok(executable)
+}
+
+/// The SHA-256 digest to verify the download with: `--luals-sha256`, or the known digest for
+/// the default version.
+///
+/// Downloads are never installed unverified.
+fn expected_sha256<'a>(opts: &'a Opts, platform: &str) -> miette::Result<&'a str> {
+    if let Some(expected) = &opts.luals_sha256 {
+        return Ok(expected);
+    }
+    let known = (opts.luals_version == DEFAULT_VERSION)
+        .then(|| {
+            DEFAULT_VERSION_SHA256
+                .iter()
+                .find(|(known, _)| *known == platform)
+        })
+        .flatten();
+    match known {
+        Some((_, digest)) => Ok(digest),
+        None => Err(usage_error(miette!(
+            help = format!(
+                "See https://github.com/LuaLS/lua-language-server/releases/tag/{} for the archive's checksum",
+                opts.luals_version
+            ),
+            "Pass `--luals-sha256` to verify the download of lua-language-server {} for {platform}",
+            opts.luals_version
+        ))),
+    }
+}
+
+/// The release platform name for this OS and architecture, like `linux-x64`.
+fn platform() -> miette::Result<&'static str> {
+    Ok(match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => "linux-x64",
+        ("linux", "aarch64") => "linux-arm64",
+        ("macos", "x86_64") => "darwin-x64",
+        ("macos", "aarch64") => "darwin-arm64",
+        ("windows", "x86_64") => "win32-x64",
+        ("windows", "x86") => "win32-ia32",
+        (os, arch) => {
+            return Err(miette!(
+                help = "Install lua-language-server yourself and pass its path with `-c`",
+                "lua-language-server doesn't publish releases for {os} on {arch}"
+            ))
+        }
+    })
+}
+
+/// The release archive for a platform, and whether it's a zip file.
+fn asset_name(version: &str, platform: &str) -> (String, bool) {
+    let is_zip = platform.starts_with("win32");
+    let extension = if is_zip { "zip" } else { "tar.gz" };
+    (
+        format!("lua-language-server-{version}-{platform}.{extension}"),
+        is_zip,
+    )
+}
+
+fn installed_executable(dir: &Path) -> Option<PathBuf> {
+    discover::server_executables(dir)
+        .into_iter()
+        .find(|candidate| candidate.is_file())
+}
+
+fn run(cmd: &mut Command, action: &str) -> miette::Result<()> {
+    let output = cmd
+        .stdin(Stdio::null())
+        .output()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to {action}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(miette!(
+            help = String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            "Failed to {action}: {}",
+            output.status
+        ))
+    }
+}
+
+/// A directory removed when dropped.
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.0) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                log::debug!("Failed to remove {}: {err}", self.0.display());
+            }
+        }
+    }
+}
+
+/// The SHA-256 digest of `data`, in lowercase hex.
+fn sha256_hex(data: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    state.iter().map(|word| format!("{word:08x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_hex() {
+        for (data, digest) in [
+            (
+                "",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                "abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            // One byte short of a block, so the length goes in a second block.
+            (
+                &"a".repeat(55),
+                "9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318",
+            ),
+            (
+                "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (
+                &"a".repeat(64),
+                "ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb",
+            ),
+            (
+                "abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+            ),
+            (
+                &"a".repeat(1_000_000),
+                "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            ),
+        ] {
+            assert_eq!(sha256_hex(data.as_bytes()), digest, "{} bytes", data.len());
+        }
+    }
+
+    #[test]
+    fn test_pinned_version_needs_checksum() {
+        let mut opts = crate::tests::parse_opts(&["--luals-version", "3.6.25"]);
+        let err = expected_sha256(&opts, "linux-x64").unwrap_err();
+        assert!(err.downcast_ref::<crate::UsageError>().is_some());
+        opts.luals_sha256 = Some("abc123".to_owned());
+        assert_eq!(expected_sha256(&opts, "linux-x64").unwrap(), "abc123");
+    }
+}
@@ -0,0 +1,5095 @@
+//! Check project diagnostics using `lua-language-server`.
+//!
+//! [`check`] runs the server and collects its diagnostics, and [`run`] also prints a report
+//! like the `lualscheck` command-line tool.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitCode;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use clap::builder::PossibleValue;
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use clap::FromArgMatches;
+use clap::Parser;
+use lsp_types::Diagnostic;
+use lsp_types::DiagnosticSeverity;
+use lsp_types::DiagnosticTag;
+use lsp_types::Location;
+use lsp_types::Position;
+use lsp_types::Range;
+use lsp_types::Url;
+use miette::miette;
+use miette::Context;
+use miette::IntoDiagnostic;
+use owo_colors::OwoColorize;
+use owo_colors::Stream::Stdout;
+use path_absolutize::Absolutize;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+mod container;
+/// Public so the allocation test in `tests/` can call [`diagnostics_file::read`] directly.
+#[doc(hidden)]
+pub mod diagnostics_file;
+mod diff;
+mod discover;
+mod docs;
+mod format;
+mod glob;
+mod install;
+mod logging;
+mod lsp;
+mod preset;
+mod process;
+mod progress;
+mod server_log;
+mod source;
+mod temp_dir;
+mod trace;
+mod version;
+
+use container::Container;
+use diff::CompactDiagnostic;
+use diff::Diff;
+use diff::JsonDiff;
+use format::BufferedStdout;
+use format::JsonDiagnostic;
+use format::RunSummary;
+use glob::Exclude;
+use glob::Glob;
+pub use preset::Preset;
+use progress::Progress;
+use progress::Spinner;
+use source::SourceCache;
+use temp_dir::TempDir;
+pub use version::Version;
+
+/// Check project diagnostics using `lua-language-server`.
+///
+/// Exits with 0 if no problems are found, 1 if diagnostics at or above `--fail` are found, 2
+/// for usage errors, and 3 if `lualscheck` or `lua-language-server` fails.
+#[derive(Debug, Clone, Parser)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    disable_version_flag = true,
+    // Otherwise clap lists every argument in usage errors once there's a subcommand.
+    override_usage = "lualscheck [OPTIONS] [PROJECT] [-- <LUALS_ARGS>...]\n       lualscheck <COMMAND>"
+)]
+pub struct Opts {
+    #[command(subcommand)]
+    pub command: Option<Subcommand>,
+
+    /// Print the version of `lualscheck` and the path and version of `lua-language-server`.
+    #[arg(short = 'V', long)]
+    pub version: bool,
+
+    /// Path to `lua-language-server` executable.
+    ///
+    /// Bare names are looked up in `$PATH`, and other relative paths are resolved from the
+    /// current directory, even with `--child-cwd`.
+    ///
+    /// Defaults to `$LUALSCHECK_SERVER` if set; this flag still takes precedence.
+    #[arg(
+        short = 'c',
+        long,
+        env = "LUALSCHECK_SERVER",
+        default_value = "lua-language-server"
+    )]
+    pub lua_language_server: PathBuf,
+
+    /// Don't look for `lua-language-server` in well-known install locations, like Neovim's
+    /// Mason, Homebrew, or the VS Code extension, when it isn't in `$PATH`.
+    #[arg(long)]
+    pub no_auto_detect: bool,
+
+    /// Download `lua-language-server` with `lualscheck install` if it isn't in `$PATH` or a
+    /// well-known install location.
+    #[arg(long)]
+    pub install_if_missing: bool,
+
+    /// The `lua-language-server` release downloaded by `lualscheck install` and
+    /// `--install-if-missing`.
+    #[arg(long, global = true, default_value = install::DEFAULT_VERSION)]
+    pub luals_version: String,
+
+    /// The expected SHA-256 checksum of the release archive downloaded by `lualscheck install`
+    /// and `--install-if-missing`.
+    ///
+    /// Downloads are always verified, so this is needed for versions other than the default, and
+    /// for platforms whose checksums for the default aren't bundled with `lualscheck`. See the
+    /// release's page on GitHub for the archive names.
+    #[arg(long, global = true)]
+    pub luals_sha256: Option<String>,
+
+    /// Fail if `lua-language-server --version` is older than this, since diagnostics and the
+    /// `--check` output vary between server versions.
+    ///
+    /// Defaults to `$LUALSCHECK_MIN_LUALS_VERSION` if set.
+    #[arg(long, env = "LUALSCHECK_MIN_LUALS_VERSION", value_name = "X.Y.Z")]
+    pub min_luals_version: Option<Version>,
+
+    /// Where `lua_language_server` came from, for error messages.
+    #[arg(skip)]
+    pub lua_language_server_source: Option<ValueSource>,
+
+    /// Error if any diagnostics at or greater than this severity are found.
+    ///
+    /// Severities may also be given as LSP numbers, from 1 for `error` to 4 for `hint`.
+    #[arg(long, default_value = "warning")]
+    pub fail: Severity,
+
+    /// Shorthand for `--fail error`.
+    #[arg(short = 'E', conflicts_with_all = ["fail", "fail_warnings"])]
+    pub fail_errors: bool,
+
+    /// Shorthand for `--fail warning`.
+    #[arg(short = 'W', conflicts_with = "fail")]
+    pub fail_warnings: bool,
+
+    /// Fail if `lua-language-server` logs any errors while checking, like files it couldn't
+    /// parse or plugins that crashed, which can mean diagnostics are missing.
+    ///
+    /// These errors are reported as a warning either way.
+    #[arg(long)]
+    pub fail_on_luals_errors: bool,
+
+    /// Fail if `lua-language-server` exits unsuccessfully, even if it wrote its results.
+    ///
+    /// By default, the results are reported and the exit status is only a warning, since the
+    /// server sometimes fails while shutting down after checking.
+    #[arg(long, conflicts_with_all = ["lsp", "stdin_json"])]
+    pub strict_luals_exit: bool,
+
+    /// Exit with a status for the most severe diagnostic displayed, rather than 1 if any
+    /// diagnostics fail.
+    ///
+    /// By default, this exits with 0 if no diagnostics are displayed, 1 for hints and info,
+    /// 4 for warnings, and 5 for errors. 2 and 3 are reserved for usage errors and for
+    /// `lualscheck` or the server failing, so they never mean the project has problems.
+    /// `--fail` still decides whether the problems are reported as an error.
+    #[arg(long)]
+    pub min_severity_exit_code: bool,
+
+    /// Change the exit status for a severity with `--min-severity-exit-code`, like `hint=0`.
+    ///
+    /// May be given multiple times. 2 and 3 are reserved, so they can't be used.
+    #[arg(
+        long,
+        value_name = "SEVERITY=CODE",
+        requires = "min_severity_exit_code"
+    )]
+    pub exit_code_map: Vec<ExitCodeMapping>,
+
+    /// Display diagnostics at or greater than this severity.
+    ///
+    /// If this is stricter than `--fail`, it's lowered to match so that failing diagnostics are
+    /// always displayed.
+    #[arg(long, default_value = "hint")]
+    pub show: Severity,
+
+    /// Shorthand for `--show hint`.
+    #[arg(short = 'H', conflicts_with = "show")]
+    pub show_hints: bool,
+
+    /// The minimum severity `lua-language-server` checks for, forwarded as `--checklevel`.
+    ///
+    /// Defaults to the more permissive of `--show` and `--fail`, or to `hint` if a `--severity`
+    /// override or `--preset` reclassifies a code as a shown severity, since that may promote
+    /// diagnostics the server would otherwise leave out.
+    #[arg(long)]
+    pub checklevel: Option<Severity>,
+
+    /// Reclassify diagnostics with a code as a different severity, like
+    /// `--severity undefined-field=error`.
+    ///
+    /// May be given multiple times. This changes how `lualscheck` displays and filters
+    /// diagnostics, not what the server checks for.
+    #[arg(long = "severity", value_name = "CODE=SEVERITY")]
+    pub severity_overrides: Vec<SeverityOverride>,
+
+    /// Reclassify and hide diagnostic codes according to a shared policy.
+    ///
+    /// `--severity`, `--exclude-code`, and `--exclude-code-from` add to or override the
+    /// preset; a `--severity` for a code the preset hides shows it again.
+    #[arg(long)]
+    pub preset: Option<Preset>,
+
+    /// Hide diagnostics with a code, like `--exclude-code lowercase-global`.
+    ///
+    /// May be given multiple times.
+    #[arg(long, value_name = "CODE")]
+    pub exclude_code: Vec<String>,
+
+    /// Read `--exclude-code` codes from a file, one per line, ignoring blank lines and `#`
+    /// comments.
+    #[arg(long, value_name = "PATH")]
+    pub exclude_code_from: Option<PathBuf>,
+
+    /// Only fail for files with more than this many diagnostics at or greater than the `--fail`
+    /// severity.
+    ///
+    /// Only the diagnostics over the limit count as problems.
+    #[arg(long, value_name = "N")]
+    pub max_per_file: Option<usize>,
+
+    /// Stop at the first diagnostic at or greater than the `--fail` severity, displaying only
+    /// that diagnostic.
+    #[arg(long, conflicts_with_all = ["max_per_file", "diff"])]
+    pub bail: bool,
+
+    /// With `--lsp`, kill `lua-language-server` as soon as it finds a diagnostic at or
+    /// greater than the `--fail` severity, rather than waiting for it to check the whole
+    /// project.
+    ///
+    /// Implies `--lsp`. Only the diagnostics published before the server is killed are
+    /// displayed.
+    #[arg(
+        long,
+        conflicts_with_all = ["docker", "keep_output", "luals_output", "no_luals_output", "retries"]
+    )]
+    pub fail_fast_server: bool,
+
+    /// Only display diagnostics which the server reports an automatic fix for, to triage what's
+    /// quickly addressable.
+    ///
+    /// This is a best-effort check of the diagnostic's `data` field. Hidden diagnostics don't
+    /// count as problems.
+    #[arg(long)]
+    pub fixable_only: bool,
+
+    /// Only display diagnostics the server tags as deprecated or unnecessary code, for cleanup
+    /// passes.
+    ///
+    /// May be given multiple times to display diagnostics with any of the tags. Hidden
+    /// diagnostics don't count as problems.
+    #[arg(long, value_name = "TAG")]
+    pub tags_only: Vec<Tag>,
+
+    /// Link locations to a hosted repository, like `https://github.com/owner/repo/blob/main`.
+    ///
+    /// Links are `<URL>/<path>#L<line>`, rendered as terminal hyperlinks when supported and
+    /// included as a `url` field in JSON output.
+    #[arg(long, value_name = "URL")]
+    pub link_base: Option<Url>,
+
+    /// Wrap messages to this many columns, instead of the terminal's width.
+    #[arg(long, value_name = "COLUMNS")]
+    pub wrap: Option<usize>,
+
+    /// Don't wrap messages, printing each message line in full.
+    #[arg(long, conflicts_with = "wrap")]
+    pub no_wrap: bool,
+
+    /// Prefix each diagnostic's severity with an icon.
+    ///
+    /// `unicode` uses symbols like `✖` and `⚠`, and `ascii` uses letters like `E:` and `W:`.
+    #[arg(long, default_value = "none")]
+    pub icons: Icons,
+
+    /// Draw a `^~~~` marker under the range of each single-line diagnostic, below its source line.
+    #[arg(long)]
+    pub carets: bool,
+
+    /// Display the `source` of each diagnostic after its severity.
+    #[arg(long)]
+    pub show_source: bool,
+
+    /// Display a link to the lua-language-server wiki's documentation for each diagnostic's
+    /// code after its message.
+    #[arg(long)]
+    pub show_rule_docs: bool,
+
+    /// Note "(has FIXME)" or "(has TODO)" after diagnostics with a `FIXME` or `TODO` comment
+    /// on their first line or the line above it, to find problems which are already known.
+    #[arg(long)]
+    pub annotate_fixmes: bool,
+
+    /// Clamp ranges ending at the start of the following line to the end of their first line.
+    ///
+    /// The server reports some end-of-line diagnostics as ending at column 0 of the next line,
+    /// like `10:5-11:1`; with this option they're displayed as `10:5-10:12` instead.
+    #[arg(long)]
+    pub normalize_ranges: bool,
+
+    /// After this many diagnostics with the same code in a file, summarize the rest on one line.
+    ///
+    /// 0 means never collapse.
+    #[arg(long, default_value = "0")]
+    pub collapse: usize,
+
+    /// Print diagnostics with the same code and message in multiple files once, followed by a
+    /// list of their other locations.
+    #[arg(long)]
+    pub aggregate_duplicates: bool,
+
+    /// Ignore diagnostics in files matching this glob, relative to the project root.
+    ///
+    /// May be given multiple times.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Read `--exclude` globs from a file, one per line, ignoring blank lines and `#` comments.
+    ///
+    /// Patterns are relative to the file's directory.
+    #[arg(long, value_name = "PATH")]
+    pub ignore_paths_file: Option<PathBuf>,
+
+    /// Display paths relative to this directory instead of the project root.
+    ///
+    /// Paths outside this directory start with `..`. Without it, paths never climb above the
+    /// project root, since only files in the project are reported.
+    #[arg(long, value_name = "DIR")]
+    pub relative_to: Option<PathBuf>,
+
+    /// Path to a `.luarc.json` configuration file, forwarded as `--configpath`.
+    ///
+    /// Relative paths are resolved from the current directory.
+    #[arg(long)]
+    pub luarc: Option<PathBuf>,
+
+    /// The working directory for `lua-language-server`, which resolves some relative settings,
+    /// like `workspace.library`, against it.
+    ///
+    /// Defaults to the project directory.
+    #[arg(long, visible_alias = "server-cwd", value_name = "DIR")]
+    pub child_cwd: Option<PathBuf>,
+
+    /// Run `lua-language-server` in a container from this image, for environments where it
+    /// can't be installed.
+    ///
+    /// The project is mounted read-only at `/workspace`, and `-c` names the executable in the
+    /// image. Paths in the diagnostics are translated back to host paths.
+    #[arg(
+        long,
+        value_name = "IMAGE",
+        conflicts_with_all = ["child_cwd", "env_remove", "install_if_missing"]
+    )]
+    pub docker: Option<String>,
+
+    /// The container runtime for `--docker`.
+    ///
+    /// Defaults to `docker`, or `podman` if Docker isn't installed.
+    #[arg(long, value_name = "PROGRAM", requires = "docker")]
+    pub container_runtime: Option<PathBuf>,
+
+    /// Set an environment variable for `lua-language-server`, like `--env LUA_PATH=./?.lua`.
+    ///
+    /// May be given multiple times.
+    #[arg(long = "env", visible_alias = "server-env", value_name = "KEY=VALUE")]
+    pub envs: Vec<EnvVar>,
+
+    /// Remove an environment variable for `lua-language-server`.
+    ///
+    /// May be given multiple times. Variables set with `--env` are kept.
+    #[arg(long, value_name = "KEY")]
+    pub env_remove: Vec<OsString>,
+
+    /// The language for `lua-language-server`'s messages, like `zh-cn`, forwarded as `--locale`.
+    #[arg(long)]
+    pub locale: Option<String>,
+
+    /// Where `lua-language-server` writes its logs and diagnostics file, forwarded as
+    /// `--logpath`.
+    ///
+    /// Defaults to a directory for the project in the user's cache directory.
+    #[arg(long, value_name = "DIR")]
+    pub luals_logpath: Option<PathBuf>,
+
+    /// Where `lua-language-server` writes generated definition files, forwarded as
+    /// `--metapath`.
+    ///
+    /// Defaults to a directory for the project in the user's cache directory.
+    #[arg(long, value_name = "DIR")]
+    pub metapath: Option<PathBuf>,
+
+    /// Only check this file or directory within the project.
+    ///
+    /// Paths are still displayed relative to the project root, and the project's
+    /// `.luarc.json` is used unless `--luarc` is given.
+    #[arg(long, value_name = "PATH")]
+    pub only: Option<PathBuf>,
+
+    /// Where to forward `lua-language-server`'s progress output.
+    #[arg(long, default_value = "progress")]
+    pub luals_output: LualsOutput,
+
+    /// Don't forward `lua-language-server`'s output; print a short progress line instead.
+    ///
+    /// Equivalent to `--luals-output none`.
+    #[arg(long, conflicts_with = "luals_output")]
+    pub no_luals_output: bool,
+
+    /// Write the report to this file instead of stdout.
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Don't display diagnostics or the server's output; only print a summary.
+    ///
+    /// The full report is still written to `--output`, if given. Machine-readable formats are
+    /// still printed, with the summary on stderr instead.
+    #[arg(long)]
+    pub summary_only: bool,
+
+    /// Print fewer logs.
+    ///
+    /// May be given multiple times. `-q` shows only errors, and `-qq` shows no logs.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub quiet: u8,
+
+    /// Don't print anything except errors running `lua-language-server` itself; only set the exit
+    /// code.
+    #[arg(long, conflicts_with_all = ["quiet", "summary_only", "timings", "verbose"])]
+    pub silent: bool,
+
+    /// Print more logs, the resolved paths, and a timing breakdown of the run to stderr.
+    ///
+    /// May be given multiple times. `-v` shows info logs, `-vv` shows debug logs, and `-vvv`
+    /// shows trace logs. `RUST_LOG` takes precedence when set.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Append debug logs with timestamps to this file, regardless of `-v` and `-q`.
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Write a Chrome trace of the run to this file, for `chrome://tracing` or Perfetto.
+    ///
+    /// Includes a span for each phase, a `filter` span for each file recording why its
+    /// diagnostics were kept or dropped, and log messages up to the debug level.
+    #[arg(long, value_name = "PATH")]
+    pub trace: Option<PathBuf>,
+
+    /// Print how long the server and `lualscheck`'s own processing took to stderr.
+    #[arg(long)]
+    pub timings: bool,
+
+    /// Keep the server's diagnostics JSON file.
+    ///
+    /// With a path, copy the file there; otherwise, print its location at the end of the run.
+    /// Without a path, this must come after the project argument.
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    pub keep_output: Option<Option<PathBuf>>,
+
+    /// Read diagnostics JSON from stdin instead of running `lua-language-server`.
+    ///
+    /// The JSON is an object mapping file URIs to arrays of LSP diagnostics, like the
+    /// server's diagnostics file, so this can format other tools' diagnostics. Only files in
+    /// the project are reported.
+    #[arg(
+        long,
+        conflicts_with_all = ["docker", "fail_fast_server", "keep_output", "lsp", "luals_threads", "memory_limit", "nice", "print_command", "projects_from", "retries", "timeout"]
+    )]
+    pub stdin_json: bool,
+
+    /// Where to look for the server's diagnostics file if its output doesn't name one, as a
+    /// glob relative to its log directory.
+    ///
+    /// Only files written during the run are used.
+    #[arg(long, value_name = "GLOB", default_value = "check.json")]
+    pub results_glob: String,
+
+    /// Fail if any diagnostic in the server's diagnostics file is malformed.
+    ///
+    /// By default, diagnostics which don't deserialize are skipped with a warning naming the
+    /// file, index, and field.
+    #[arg(long, conflicts_with = "lsp")]
+    pub strict_parse: bool,
+
+    /// Write run metadata as JSON to this file, regardless of `--format` and whether the run
+    /// fails.
+    ///
+    /// Includes the number of diagnostics displayed and failing, counts by severity, the
+    /// server's version, timings, and the error if `lualscheck` or the server failed.
+    #[arg(long, value_name = "PATH")]
+    pub summary_json: Option<PathBuf>,
+
+    /// Run `lua-language-server` as a language server, opening each Lua file in the project
+    /// and collecting the diagnostics it publishes, instead of running `--check` and reading
+    /// its results file.
+    ///
+    /// The server's diagnostics are collected once it's published them for every file and
+    /// has been idle briefly.
+    #[arg(
+        long,
+        conflicts_with_all = ["checklevel", "docker", "keep_output", "luals_output", "no_luals_output", "results_glob", "retries"]
+    )]
+    pub lsp: bool,
+
+    /// Kill `lua-language-server` and its workers if it runs for longer than this, like `10m`.
+    #[arg(long, value_name = "DURATION")]
+    pub timeout: Option<humantime::Duration>,
+
+    /// Run `lua-language-server` at this niceness, from -20 to 19, so it doesn't starve other
+    /// processes.
+    ///
+    /// On Windows, positive values use the below-normal priority class, and negative values
+    /// the above-normal priority class.
+    #[arg(
+        long,
+        value_name = "N",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19),
+        conflicts_with = "docker"
+    )]
+    pub nice: Option<i32>,
+
+    /// Limit the memory `lua-language-server` can use, like `4G` or `512M`.
+    ///
+    /// This limits its address space on Unix, its job's committed memory on Windows, and its
+    /// container's memory with `--docker`.
+    #[arg(long, value_name = "SIZE")]
+    pub memory_limit: Option<MemorySize>,
+
+    /// Check files on this many threads, for `lua-language-server` releases which support
+    /// `--num_threads`.
+    ///
+    /// Older releases ignore it.
+    #[arg(long, value_name = "N", conflicts_with = "lsp")]
+    pub luals_threads: Option<NonZeroUsize>,
+
+    /// Run `lua-language-server` again, up to this many times, if it crashes without writing a
+    /// diagnostics file.
+    ///
+    /// Each retry waits a second longer than the last. Timeouts aren't retried.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub retries: u32,
+
+    /// Print the `lua-language-server` command line and working directory, then exit without
+    /// running it.
+    #[arg(long)]
+    pub print_command: bool,
+
+    /// Output format.
+    ///
+    /// With formats other than `human`, failing runs exit with a nonzero status without
+    /// printing an error message.
+    #[arg(long, default_value = "human")]
+    pub format: Format,
+
+    /// How to order files: by `path` (case-insensitively), most recently modified first
+    /// (`mtime`), or most diagnostics first (`count`).
+    ///
+    /// By default, files are in the order of their URIs. Each file's diagnostics are always in
+    /// order of their positions.
+    #[arg(long, value_name = "ORDER")]
+    pub sort_files: Option<SortFiles>,
+
+    /// Compare against a previous `--format json` report, displaying new, fixed, and unchanged
+    /// diagnostics.
+    ///
+    /// Diagnostics are matched by path, code, and message, ignoring their positions. Only new
+    /// diagnostics count as problems.
+    #[arg(long, value_name = "PATH")]
+    pub diff: Option<PathBuf>,
+
+    /// Only fail on diagnostics which aren't in this baseline, a `--format json` report
+    /// committed to the project.
+    ///
+    /// Diagnostics are matched by path, code, and message, ignoring their positions, like
+    /// `--diff`. Only new diagnostics are displayed. Defaults to `$LUALSCHECK_BASELINE` if set.
+    #[arg(
+        long,
+        env = "LUALSCHECK_BASELINE",
+        value_name = "BASELINE",
+        conflicts_with_all = ["bail", "diff", "max_per_file"]
+    )]
+    pub fail_on_new_only: Option<PathBuf>,
+
+    /// Rewrite the `--fail-on-new-only` baseline with the current diagnostics, rather than
+    /// comparing against it.
+    #[arg(long, requires = "fail_on_new_only")]
+    pub update_baseline: bool,
+
+    /// Instead of displaying diagnostics, print the number of diagnostics at or greater than the
+    /// `--fail` severity.
+    #[arg(long)]
+    pub count: bool,
+
+    /// Like `--count`, but print counts per severity or code on one line, like
+    /// `error=3 warning=12`.
+    #[arg(long, value_name = "GROUPING")]
+    pub count_by: Option<CountBy>,
+
+    /// Instead of displaying diagnostics, list the N files with the most diagnostics at or
+    /// greater than the `--fail` severity.
+    #[arg(long, value_name = "N")]
+    pub top: Option<usize>,
+
+    /// Instead of displaying diagnostics, summarize diagnostic counts per directory.
+    ///
+    /// `dir=N` groups files by their first N directories relative to the project root
+    /// (default 1).
+    #[arg(long, value_name = "dir[=DEPTH]")]
+    pub summary_by: Option<SummaryBy>,
+
+    /// Instead of displaying diagnostics, list the diagnostic codes found, most frequent first.
+    #[arg(long)]
+    pub list_codes: bool,
+
+    /// Path to the project to check.
+    ///
+    /// Defaults to `$LUALSCHECK_PROJECT` if set; this argument still takes precedence.
+    #[arg(env = "LUALSCHECK_PROJECT", default_value = ".")]
+    pub project: PathBuf,
+
+    /// Check each project listed in this file instead of the project argument, one path per
+    /// line, ignoring blank lines and `#` comments.
+    ///
+    /// Paths are relative to the file's directory, and diagnostics are displayed relative to
+    /// the current directory unless `--relative-to` is given.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["only", "output", "summary_json", "diff", "keep_output", "print_command"]
+    )]
+    pub projects_from: Option<PathBuf>,
+
+    /// Extra arguments for `lua-language-server`, passed after `--`.
+    #[arg(last = true, value_name = "LUALS_ARGS")]
+    pub luals_args: Vec<OsString>,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Subcommand {
+    /// Download a `lua-language-server` release for this platform into the cache directory
+    /// and print the path to its executable.
+    Install,
+    /// Describe a diagnostic code, how to fix it, and how to suppress it, without running a
+    /// check.
+    Explain {
+        /// The diagnostic code, like `undefined-field`.
+        code: String,
+    },
+}
+
+/// Server arguments set by `lualscheck`, and the options which control them.
+const RESERVED_LUALS_ARGS: &[(&str, &str)] = &[
+    ("--check", "the project argument or `--only`"),
+    ("--check_out_path", "`--keep-output`"),
+    ("--checklevel", "`--checklevel`"),
+    ("--configpath", "`--luarc`"),
+    ("--locale", "`--locale`"),
+    ("--logpath", "`--luals-logpath`"),
+    ("--metapath", "`--metapath`"),
+    ("--num_threads", "`--luals-threads`"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable output.
+    Human,
+    /// A JSON array of diagnostics.
+    Json,
+    /// One JSON object per line for each diagnostic.
+    Ndjson,
+    /// CSV with one row per diagnostic.
+    Csv,
+    /// TeamCity inspection service messages.
+    Teamcity,
+    /// Checkstyle XML.
+    Checkstyle,
+    /// `path:line:col: severity: message` lines for Emacs's `compilation-mode`.
+    Emacs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Icons {
+    /// Unicode symbols and emoji.
+    Unicode,
+    /// ASCII letters.
+    Ascii,
+    /// No icons.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    #[serde(rename = "info")]
+    Information,
+    Hint,
+}
+
+/// Where to forward `lua-language-server`'s output for `--luals-output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LualsOutput {
+    /// Draw a progress bar on stderr, forwarding other output.
+    Progress,
+    /// Forward output to stdout unchanged.
+    Stdout,
+    /// Forward output to stderr unchanged.
+    Stderr,
+    /// Don't forward output.
+    None,
+}
+
+/// A diagnostic tag for `--tags-only`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Tag {
+    /// Uses of deprecated code.
+    Deprecated,
+    /// Unused or unreachable code.
+    Unnecessary,
+}
+
+impl Tag {
+    fn to_lsp(self) -> DiagnosticTag {
+        match self {
+            Tag::Deprecated => DiagnosticTag::DEPRECATED,
+            Tag::Unnecessary => DiagnosticTag::UNNECESSARY,
+        }
+    }
+}
+
+/// How to order files for `--sort-files`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortFiles {
+    Path,
+    Mtime,
+    Count,
+}
+
+/// How to group diagnostics for `--count-by`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CountBy {
+    Severity,
+    Code,
+}
+
+/// How to group diagnostics for `--summary-by`.
+#[derive(Debug, Clone)]
+pub struct SummaryBy {
+    /// How many directories below the project root to group by.
+    pub depth: usize,
+}
+
+impl FromStr for SummaryBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, depth) = match s.split_once('=') {
+            Some((kind, depth)) => (kind, Some(depth)),
+            None => (s, None),
+        };
+        if kind != "dir" {
+            return Err(format!(
+                "unknown summary grouping {kind:?}; expected \"dir\""
+            ));
+        }
+        let depth = match depth {
+            Some(depth) => depth
+                .parse()
+                .map_err(|err| format!("invalid depth {depth:?}: {err}"))?,
+            None => 1,
+        };
+        Ok(Self { depth })
+    }
+}
+
+/// A size in bytes for `--memory-limit`, like `512M` or `4G`.
+///
+/// Suffixes are binary multiples, so `1K` is 1024 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemorySize(pub u64);
+
+impl FromStr for MemorySize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(trimmed.len());
+        let (number, suffix) = trimmed.split_at(split);
+        let suffix = suffix.trim().to_ascii_lowercase();
+        let exponent = match suffix.trim_end_matches("ib").trim_end_matches('b') {
+            "" => 0,
+            "k" => 1,
+            "m" => 2,
+            "g" => 3,
+            "t" => 4,
+            _ => {
+                return Err(format!(
+                    "unknown size suffix {suffix:?} in {s:?}; expected K, M, G, or T"
+                ))
+            }
+        };
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("expected a size like `512M` or `4G`: {s:?}"))?;
+        let bytes = number * 1024f64.powi(exponent);
+        if bytes < 1.0 || bytes >= u64::MAX as f64 {
+            return Err(format!("size out of range: {s:?}"));
+        }
+        Ok(Self(bytes as u64))
+    }
+}
+
+impl Display for MemorySize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (divisor, suffix) = [
+            (1 << 40, "T"),
+            (1 << 30, "G"),
+            (1 << 20, "M"),
+            (1 << 10, "K"),
+        ]
+        .into_iter()
+        .find(|(divisor, _)| self.0.is_multiple_of(*divisor))
+        .unwrap_or((1, ""));
+        write!(f, "{}{suffix}", self.0 / divisor)
+    }
+}
+
+/// An `--env` variable for the server.
+#[derive(Debug, Clone)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for EnvVar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Only split on the first `=`; values may contain more.
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(Self {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            }),
+            _ => Err(format!("expected KEY=VALUE: {s:?}")),
+        }
+    }
+}
+
+/// A `--severity` override for a diagnostic code.
+#[derive(Debug, Clone)]
+pub struct SeverityOverride {
+    pub code: String,
+    pub severity: Severity,
+}
+
+impl FromStr for SeverityOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (code, severity) = s.split_once('=').ok_or_else(|| {
+            format!("expected CODE=SEVERITY, like `undefined-field=error`: {s:?}")
+        })?;
+        let severity = <Severity as clap::ValueEnum>::from_str(severity, true)?;
+        Ok(Self {
+            code: code.to_owned(),
+            severity,
+        })
+    }
+}
+
+/// An `--exit-code-map` entry, the exit status for a severity.
+#[derive(Debug, Clone)]
+pub struct ExitCodeMapping {
+    pub severity: Severity,
+    pub code: u8,
+}
+
+impl FromStr for ExitCodeMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (severity, code) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected SEVERITY=CODE, like `warning=4`: {s:?}"))?;
+        let severity = <Severity as clap::ValueEnum>::from_str(severity, true)?;
+        let code = code
+            .parse()
+            .map_err(|err| format!("invalid exit code {code:?}: {err}"))?;
+        if code == USAGE_ERROR || code == TOOL_FAILURE {
+            return Err(format!(
+                "exit code {code} is reserved for {}",
+                if code == USAGE_ERROR {
+                    "usage errors"
+                } else {
+                    "lualscheck or lua-language-server failing"
+                }
+            ));
+        }
+        Ok(Self { severity, code })
+    }
+}
+
+impl Severity {
+    /// The exit status for `--min-severity-exit-code` if this is the most severe diagnostic
+    /// displayed.
+    fn exit_code(self, exit_code_map: &[ExitCodeMapping]) -> ExitCode {
+        let code = exit_code_map
+            .iter()
+            .rev()
+            .find(|mapping| mapping.severity == self)
+            .map(|mapping| mapping.code)
+            .unwrap_or(match self {
+                Severity::Error => 5,
+                Severity::Warning => 4,
+                Severity::Information | Severity::Hint => PROBLEMS_FOUND,
+            });
+        ExitCode::from(code)
+    }
+
+    fn from_lsp(severity: DiagnosticSeverity) -> Option<Self> {
+        if severity == DiagnosticSeverity::ERROR {
+            Some(Self::Error)
+        } else if severity == DiagnosticSeverity::WARNING {
+            Some(Self::Warning)
+        } else if severity == DiagnosticSeverity::INFORMATION {
+            Some(Self::Information)
+        } else if severity == DiagnosticSeverity::HINT {
+            Some(Self::Hint)
+        } else {
+            None
+        }
+    }
+
+    /// The name of this severity for `lua-language-server --checklevel`.
+    fn checklevel(self) -> &'static str {
+        match self {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+            Severity::Information => "Information",
+            Severity::Hint => "Hint",
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Information => "info",
+                Severity::Hint => "hint",
+            }
+        )
+    }
+}
+
+impl clap::ValueEnum for Severity {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Error, Self::Warning, Self::Information, Self::Hint]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            // The aliases are LSP's numeric severities.
+            Severity::Error => Some(PossibleValue::new("error").alias("1")),
+            Severity::Warning => Some(PossibleValue::new("warning").alias("2")),
+            Severity::Information => Some(PossibleValue::new("info").alias("3")),
+            Severity::Hint => Some(PossibleValue::new("hint").alias("4")),
+        }
+    }
+}
+
+impl From<Severity> for DiagnosticSeverity {
+    fn from(value: Severity) -> Self {
+        match value {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Information => DiagnosticSeverity::INFORMATION,
+            Severity::Hint => DiagnosticSeverity::HINT,
+        }
+    }
+}
+
+impl Opts {
+    /// Parse options from command-line arguments, like `Opts::command().get_matches()`.
+    ///
+    /// Unlike `Opts::from_arg_matches`, this applies shorthands like `-E` and `-H`.
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let mut opts = Self::from_arg_matches(matches)?;
+        opts.lua_language_server_source = matches.value_source("lua_language_server");
+        if opts.fail_errors {
+            opts.fail = Severity::Error;
+        } else if opts.fail_warnings {
+            opts.fail = Severity::Warning;
+        }
+        if opts.show_hints {
+            opts.show = Severity::Hint;
+        }
+        if opts.fail_fast_server {
+            opts.lsp = true;
+        }
+        Ok(opts)
+    }
+}
+
+/// The exit status when diagnostics at or above `--fail` are found.
+pub const PROBLEMS_FOUND: u8 = 1;
+
+/// The exit status for usage errors, like `clap`'s.
+pub const USAGE_ERROR: u8 = 2;
+
+/// The exit status when `lualscheck` or `lua-language-server` fails, as opposed to the
+/// project having problems.
+pub const TOOL_FAILURE: u8 = 3;
+
+/// An error in the options `lualscheck` was given, which [`run`] exits with [`USAGE_ERROR`] for.
+///
+/// These are found before `lua-language-server` is run.
+#[derive(Debug)]
+pub struct UsageError(miette::Report);
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl miette::Diagnostic for UsageError {
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.0.help()
+    }
+}
+
+/// Wrap an error as a [`UsageError`].
+fn usage_error(report: miette::Report) -> miette::Report {
+    miette::Report::new(UsageError(report))
+}
+
+/// Run `lualscheck` as a command-line tool, initializing logging and printing the report.
+///
+/// Errors are failures of `lualscheck` or `lua-language-server`, for [`TOOL_FAILURE`]; finding
+/// problems is reported with [`PROBLEMS_FOUND`] instead, and [`UsageError`]s are printed here
+/// and reported with [`USAGE_ERROR`].
+pub fn run(opts: Opts) -> miette::Result<ExitCode> {
+    if let Some(path) = &opts.trace {
+        trace::init(path);
+    }
+    logging::init(&opts)?;
+
+    let result = run_command(opts);
+    trace::finish()?;
+    match result {
+        Err(err) if err.downcast_ref::<UsageError>().is_some() => {
+            eprintln!("Error: {err:?}");
+            Ok(ExitCode::from(USAGE_ERROR))
+        }
+        result => result,
+    }
+}
+
+/// The results of a `lua-language-server --check` run, filtered by the options given to
+/// [`check`].
+#[derive(Debug)]
+pub struct CheckReport {
+    /// The displayed diagnostics, grouped by file.
+    pub files: Vec<FileDiagnostics>,
+    /// Counts of the displayed diagnostics by severity.
+    pub counts: SeverityCounts,
+    /// The number of diagnostics counted towards failing the run, after `--max-per-file`.
+    ///
+    /// This doesn't account for `--diff`, which is compared when the report is rendered.
+    pub failing: usize,
+    /// Files with more failing diagnostics than `--max-per-file`, and how many they have.
+    pub over_budget: Vec<(PathBuf, usize)>,
+    /// Set when `--bail` stopped at a failing diagnostic.
+    pub bailed: bool,
+    /// The directory the files' paths are relative to: the project or `--relative-to`.
+    pub relative_base: PathBuf,
+    /// The diagnostics file written by the server, or `None` if it found no problems or ran
+    /// as a language server for `--lsp`.
+    pub diagnostics_file: Option<PathBuf>,
+    /// The output of `lua-language-server --version`, if it was needed.
+    pub server_version: Option<String>,
+    /// The errors the server logged while checking, which may mean diagnostics are missing.
+    pub server_errors: Vec<String>,
+    /// How the server exited, if it exited unsuccessfully after writing its results.
+    pub server_exit: Option<String>,
+    /// The number of files the server checked, if it said.
+    pub files_checked: Option<usize>,
+    phases: Phases,
+}
+
+impl CheckReport {
+    /// The displayed diagnostics and their paths.
+    pub fn diagnostics(&self) -> impl Iterator<Item = (&Path, &Diagnostic)> {
+        self.files.iter().flat_map(|file| {
+            file.diagnostics
+                .iter()
+                .map(|diagnostic| (file.path.as_path(), diagnostic))
+        })
+    }
+}
+
+/// Run `lua-language-server --check` and collect the diagnostics it finds, without printing a
+/// report.
+pub fn check(opts: &Opts) -> miette::Result<CheckReport> {
+    Invocation::new(opts)?.check(opts)
+}
+
+fn run_command(opts: Opts) -> miette::Result<ExitCode> {
+    if opts.version {
+        print_version(&opts)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Subcommand::Explain { code }) = &opts.command {
+        print!("{}", docs::explain(code, textwrap::termwidth()));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Subcommand::Install) = opts.command {
+        let executable = install::install(&opts)?;
+        println!("{}", executable.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(projects_from) = &opts.projects_from {
+        return check_projects(&opts, projects_from);
+    }
+
+    let started = Instant::now();
+    let mut summary = None;
+    let result = check_and_report(&opts, &mut summary);
+    // Written here, rather than where the run ends, so failed runs get a summary too.
+    if let Some(path) = &opts.summary_json {
+        let mut summary = summary.unwrap_or_else(|| RunSummary {
+            diagnostics: 0,
+            failing: 0,
+            fail: opts.fail,
+            severities: SeverityCounts::default(),
+            server_version: None,
+            luals_version: None,
+            elapsed_seconds: started.elapsed().as_secs_f64(),
+            server_seconds: 0.0,
+            error: None,
+        });
+        summary.error = result.as_ref().err().map(|err| {
+            err.chain()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": ")
+        });
+        match write_summary_json(path, &summary) {
+            Ok(()) => {}
+            // Don't hide why the run failed.
+            Err(err) if result.is_err() => log::warn!("{err}"),
+            Err(err) => return Err(err),
+        }
+    }
+    result
+}
+
+/// Check the project and print the report, filling in `summary` for `--summary-json` once the
+/// server's run is over.
+fn check_and_report(opts: &Opts, summary: &mut Option<RunSummary>) -> miette::Result<ExitCode> {
+    if opts.format != Format::Human || opts.output.is_some() {
+        // Keep machine-readable output and report files free of escape codes.
+        owo_colors::set_override(false);
+    }
+
+    // Create the report file up front so a bad path is reported before running the server.
+    let mut out: Box<dyn Write> = match &opts.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to create output file: {path:?}"))?,
+        )),
+        None if opts.silent || (opts.summary_only && opts.format == Format::Human) => {
+            Box::new(std::io::sink())
+        }
+        None => Box::new(BufferedStdout::default()),
+    };
+
+    let invocation = Invocation::new(opts)?;
+
+    if opts.print_command {
+        println!("{}", CommandLine(&invocation.cmd));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    process::handle_interrupts();
+    let mut report = invocation.check(opts)?;
+
+    if let Some(baseline) = &opts.fail_on_new_only {
+        apply_baseline(opts, &mut report, baseline)?;
+    }
+
+    // Without a diagnostics file, the server found no problems; the report is still printed,
+    // so machine-readable formats are never empty.
+    if report.diagnostics_file.is_none() && opts.keep_output.is_some() {
+        log::warn!(
+            "No problems found, so lua-language-server didn't write a diagnostics file to keep"
+        );
+    }
+
+    let fail: DiagnosticSeverity = opts.fail.into();
+    let render = RenderOpts::new(opts);
+    let files = &report.files;
+    let relative_base = &report.relative_base;
+    let mut found_diagnostics = report.failing;
+
+    if let Some(previous) = &opts.diff {
+        let diff = Diff::new(files, diff::read_previous(previous)?);
+        found_diagnostics = diff
+            .new
+            .iter()
+            .filter(|(_, diagnostic)| {
+                diagnostic
+                    .severity
+                    .map(|severity| severity <= fail)
+                    .unwrap_or(false)
+            })
+            .count();
+        let json = |diagnostics: &[(&Path, &Diagnostic)]| {
+            diagnostics
+                .iter()
+                .map(|(path, diagnostic)| {
+                    JsonDiagnostic::new(path, diagnostic, opts.link_base.as_ref())
+                })
+                .collect::<Vec<_>>()
+        };
+        match opts.format {
+            Format::Human => write_diff_human(&mut out, &render, relative_base, &diff)?,
+            Format::Json => format::write_json(
+                &mut out,
+                &JsonDiff {
+                    new: json(&diff.new),
+                    fixed: diff.fixed.clone(),
+                    unchanged: json(&diff.unchanged),
+                },
+            )?,
+            _ => unreachable!("Checked by `check_usage`"),
+        }
+    } else if let Some(count_by) = opts.count_by {
+        writeln!(out, "{}", count_failing(files, fail, count_by)).into_diagnostic()?;
+    } else if opts.count {
+        writeln!(out, "{found_diagnostics}").into_diagnostic()?;
+    } else if let Some(top) = opts.top {
+        let ranking = rank_files(files, fail, top);
+        match opts.format {
+            Format::Human => {
+                write!(out, "{}", FileRanking(&ranking)).into_diagnostic()?;
+            }
+            Format::Json => format::write_json(&mut out, &ranking)?,
+            _ => unreachable!("Checked by `check_usage`"),
+        }
+    } else if let Some(summary_by) = &opts.summary_by {
+        let summary = summarize_directories(files, summary_by.depth);
+        match opts.format {
+            Format::Human => {
+                write!(out, "{}", DirectoryTable(&summary)).into_diagnostic()?;
+            }
+            Format::Json => format::write_json(&mut out, &summary)?,
+            _ => unreachable!("Checked by `check_usage`"),
+        }
+    } else if opts.list_codes {
+        write!(out, "{}", CodeList::new(files)).into_diagnostic()?;
+    } else {
+        match opts.format {
+            Format::Human => write_human(&mut out, opts, &render, relative_base, files)?,
+            Format::Json => format::write_json(
+                &mut out,
+                &format::json_diagnostics(files, opts.link_base.as_ref()).collect::<Vec<_>>(),
+            )?,
+            Format::Ndjson => format::write_ndjson(&mut out, files, opts.link_base.as_ref())?,
+            Format::Csv => format::write_csv(&mut out, files)?,
+            Format::Teamcity => format::write_teamcity(&mut out, files)?,
+            Format::Checkstyle => format::write_checkstyle(&mut out, files)?,
+            Format::Emacs => format::write_emacs(&mut out, files)?,
+        }
+    }
+
+    out.flush().into_diagnostic()?;
+
+    let counts = &report.counts;
+    if opts.summary_only {
+        // Keep machine-readable output on stdout parseable.
+        let summary_line = |line: String| {
+            if opts.format == Format::Human {
+                println!("{line}");
+            } else {
+                eprintln!("{line}");
+            }
+        };
+        if counts.total() == 0 {
+            summary_line("No problems found".to_owned());
+        } else {
+            summary_line(format!("Found {counts}"));
+        }
+        if found_diagnostics == 0 && counts.total() > 0 {
+            summary_line(format!("No problems at or above {} severity", opts.fail));
+        }
+    } else if counts.total() == 0 && found_diagnostics == 0 {
+        print_no_problems(opts, &report);
+    }
+
+    report.phases.end("render");
+    report.phases.print(opts);
+
+    *summary = Some(run_summary(
+        opts,
+        report.server_version.as_deref(),
+        &report.phases,
+        counts.clone(),
+        found_diagnostics,
+    ));
+
+    if let (Some(None), Some(path)) = (&opts.keep_output, &report.diagnostics_file) {
+        eprintln!("Diagnostics file: {}", path.display());
+    }
+
+    check_server_exit(opts, &report)?;
+
+    let severity_exit_code = opts.min_severity_exit_code.then(|| {
+        counts.most_severe().map_or(ExitCode::SUCCESS, |severity| {
+            severity.exit_code(&opts.exit_code_map)
+        })
+    });
+
+    if found_diagnostics > 0 {
+        if opts.silent || opts.format != Format::Human {
+            // The exit status is enough; don't clutter machine-readable output with a report.
+            return Ok(severity_exit_code.unwrap_or(ExitCode::from(PROBLEMS_FOUND)));
+        }
+        if opts.format == Format::Human
+            && opts.output.is_none()
+            && !opts.summary_only
+            && !opts.count
+            && opts.count_by.is_none()
+        {
+            // Separate the report from the error message.
+            let _ = writeln!(std::io::stdout());
+        }
+        // Print the error here rather than returning it, which would exit with
+        // `TOOL_FAILURE`.
+        eprintln!(
+            "Error: {:?}",
+            failure_report(opts, &report, found_diagnostics)
+        );
+        Ok(severity_exit_code.unwrap_or(ExitCode::from(PROBLEMS_FOUND)))
+    } else {
+        check_server_errors(opts, &report)?;
+        Ok(severity_exit_code.unwrap_or(ExitCode::SUCCESS))
+    }
+}
+
+/// Remove the diagnostics in the `--fail-on-new-only` baseline from the report, or rewrite the
+/// baseline for `--update-baseline`.
+fn apply_baseline(opts: &Opts, report: &mut CheckReport, baseline: &Path) -> miette::Result<()> {
+    if opts.update_baseline {
+        diff::write_baseline(baseline, &report.files)?;
+        log::info!(
+            "Wrote {} diagnostics to the baseline {}",
+            report.counts.total(),
+            baseline.display()
+        );
+    } else if !baseline.exists() {
+        return Err(miette!(
+            help = "Create it with `--update-baseline`",
+            "Baseline doesn't exist: {baseline:?}"
+        ));
+    }
+    let previous = diff::read_previous(baseline)?;
+    let diff = Diff::new(&report.files, previous);
+
+    let fail: DiagnosticSeverity = opts.fail.into();
+    let mut files = Vec::<FileDiagnostics>::new();
+    let mut counts = SeverityCounts::default();
+    let mut failing = 0;
+    for (path, diagnostic) in diff.new {
+        counts.add(diagnostic.severity);
+        if diagnostic.severity.is_some_and(|severity| severity <= fail) {
+            failing += 1;
+        }
+        // New diagnostics are in the report's order, so each file's are together.
+        match files.last_mut() {
+            Some(file) if file.path == path => file.diagnostics.push(diagnostic.clone()),
+            _ => files.push(FileDiagnostics {
+                path: path.to_owned(),
+                diagnostics: vec![diagnostic.clone()],
+            }),
+        }
+    }
+    log::info!(
+        "{} diagnostics are in the baseline {}",
+        diff.unchanged.len(),
+        baseline.display()
+    );
+
+    report.files = files;
+    report.counts = counts;
+    report.failing = failing;
+    Ok(())
+}
+
+/// The error for a run which found failing diagnostics.
+fn failure_report(opts: &Opts, report: &CheckReport, found_diagnostics: usize) -> miette::Report {
+    // `--stdin-json` diagnostics may come from any tool.
+    let found = if opts.stdin_json {
+        "Found"
+    } else {
+        "lua-language-server found"
+    };
+    if report.bailed {
+        return miette!("{found} a problem (stopped at the first one for `--bail`)");
+    }
+    if opts.diff.is_some() || opts.fail_on_new_only.is_some() {
+        return miette!("{found} {} new problems", found_diagnostics);
+    }
+    match opts.max_per_file {
+        Some(max) => miette!(
+            help = report
+                .over_budget
+                .iter()
+                .map(|(path, count)| format!(
+                    "{}: {count} problems ({} over)",
+                    path.display(),
+                    count - max
+                ))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "{found} {} problems over the budget of {max} per file",
+            found_diagnostics
+        ),
+        None => miette!("{found} {} problems", found_diagnostics),
+    }
+}
+
+/// Check each project listed in the `--projects-from` file in turn, failing if any of them
+/// fail.
+fn check_projects(opts: &Opts, projects_from: &Path) -> miette::Result<ExitCode> {
+    let projects = read_project_list(projects_from)?;
+    if projects.is_empty() {
+        log::warn!("No projects listed in {}", projects_from.display());
+    }
+
+    let mut failed = Vec::new();
+    // Set when checking a project fails, rather than finding problems.
+    let mut tool_failed = false;
+    for project in &projects {
+        let mut project_opts = opts.clone();
+        project_opts.projects_from = None;
+        project_opts.project = project.clone();
+        // Keep paths from different projects apart.
+        project_opts
+            .relative_to
+            .get_or_insert_with(|| PathBuf::from("."));
+
+        match run_command(project_opts) {
+            Ok(code) if code == ExitCode::SUCCESS => {}
+            Ok(_) => failed.push(project),
+            Err(err) => {
+                if !opts.silent {
+                    eprintln!("{err:?}");
+                }
+                failed.push(project);
+                tool_failed = true;
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        return Ok(ExitCode::SUCCESS);
+    }
+    let err = miette!(
+        help = failed
+            .iter()
+            .map(|project| project.display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        "{} of {} projects failed",
+        failed.len(),
+        projects.len()
+    );
+    if tool_failed {
+        return Err(err);
+    }
+    if !opts.silent {
+        eprintln!("Error: {err:?}");
+    }
+    Ok(ExitCode::from(PROBLEMS_FOUND))
+}
+
+/// Read a `--projects-from` file, resolving paths relative to its directory.
+fn read_project_list(path: &Path) -> miette::Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read project list: {}", path.display()))?;
+    let base = path
+        .absolutize()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to make path absolute: {path:?}"))?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let project = base
+                .join(line)
+                .absolutize()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to make path absolute: {line:?}"))?
+                .into_owned();
+            if !project.is_dir() {
+                return Err(miette!(
+                    "Project listed in {} isn't a directory: {line:?}",
+                    path.display()
+                ));
+            }
+            Ok(project)
+        })
+        .collect()
+}
+
+/// Read diagnostic codes for `--exclude-code-from`.
+fn read_code_file(path: &Path) -> miette::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read code file: {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// Print a success message for runs without any problems, when the default report is
+/// displayed in a terminal.
+fn print_no_problems(opts: &Opts, report: &CheckReport) {
+    let default_report = opts.format == Format::Human
+        && opts.output.is_none()
+        && opts.diff.is_none()
+        && !opts.count
+        && opts.count_by.is_none()
+        && opts.top.is_none()
+        && opts.summary_by.is_none()
+        && !opts.list_codes;
+    if !default_report || opts.summary_only || opts.silent || !std::io::stdout().is_terminal() {
+        return;
+    }
+    let files_checked = match report.files_checked {
+        Some(1) => " (1 file checked)".to_owned(),
+        Some(count) => format!(" ({count} files checked)"),
+        None => String::new(),
+    };
+    let problems = if opts.fail_on_new_only.is_some() {
+        "new problems"
+    } else {
+        "problems"
+    };
+    println!(
+        "{} No {problems} found in {}{files_checked}",
+        "✓".if_supports_color(Stdout, |text| text.green()),
+        opts.project.display()
+    );
+}
+
+/// A `lua-language-server --check` command and the paths resolved from [`Opts`] to run it.
+struct Invocation {
+    cmd: Command,
+    lua_language_server: PathBuf,
+    /// The `--docker` container the server runs in.
+    container: Option<Container>,
+    project: PathBuf,
+    /// The project or `--only` path.
+    check_path: PathBuf,
+    relative_base: PathBuf,
+    excludes: Vec<Exclude>,
+    /// Diagnostic codes to hide, from `--preset`, `--exclude-code`, and
+    /// `--exclude-code-from`.
+    excluded_codes: HashSet<String>,
+    logpath: Option<PathBuf>,
+    /// Where to look for the diagnostics file in the log directory, for `--results-glob`.
+    results_glob: Glob,
+    show: DiagnosticSeverity,
+    fail: DiagnosticSeverity,
+}
+
+/// Check options which conflict, before anything is run.
+fn check_usage(opts: &Opts) -> miette::Result<()> {
+    let human_or_json = matches!(opts.format, Format::Human | Format::Json);
+    for (option, given) in [
+        ("--diff", opts.diff.is_some()),
+        ("--top", opts.top.is_some()),
+        ("--summary-by", opts.summary_by.is_some()),
+    ] {
+        if given && !human_or_json {
+            return Err(usage_error(miette!(
+                "{option} only supports the `human` and `json` formats"
+            )));
+        }
+    }
+
+    for arg in &opts.luals_args {
+        let arg_str = arg.to_string_lossy();
+        let name = arg_str.split_once('=').map_or(&*arg_str, |(name, _)| name);
+        if let Some((reserved, option)) = RESERVED_LUALS_ARGS
+            .iter()
+            .find(|(reserved, _)| *reserved == name)
+        {
+            return Err(usage_error(miette!(
+                help = format!("Use {option} instead"),
+                "`{reserved}` is set by lualscheck and can't be passed to lua-language-server after `--`"
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl Invocation {
+    fn new(opts: &Opts) -> miette::Result<Self> {
+        check_usage(opts)?;
+
+        let fail: DiagnosticSeverity = opts.fail.into();
+        let mut show: DiagnosticSeverity = opts.show.into();
+
+        if fail > show {
+            log::warn!(
+                "`--show {}` is stricter than `--fail {}`; showing diagnostics at or above {} severity so that failing diagnostics are displayed",
+                opts.show,
+                opts.fail,
+                opts.fail
+            );
+            show = fail;
+        }
+
+        let current_dir = std::env::current_dir().into_diagnostic()?;
+        let project_absolute = opts
+            .project
+            .absolutize_from(&current_dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to make path absolute: {:?}", opts.project))?;
+
+        let relative_base = match &opts.relative_to {
+            Some(relative_to) => {
+                let relative_to_absolute = relative_to
+                    .absolutize_from(&current_dir)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to make path absolute: {relative_to:?}"))?;
+                if !relative_to_absolute.is_dir() {
+                    return Err(usage_error(miette!(
+                        "--relative-to directory doesn't exist: {relative_to:?}"
+                    )));
+                }
+                relative_to_absolute
+            }
+            None => project_absolute.clone(),
+        };
+
+        let check_absolute = match &opts.only {
+            Some(only) => {
+                let only_absolute = only
+                    .absolutize_from(&current_dir)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to make path absolute: {only:?}"))?;
+                if !path_starts_with(&only_absolute, &project_absolute) {
+                    return Err(usage_error(miette!(
+                        help = format!("The project is {}", project_absolute.display()),
+                        "--only path isn't inside the project: {only:?}"
+                    )));
+                }
+                if !only_absolute.exists() {
+                    return Err(usage_error(miette!("--only path doesn't exist: {only:?}")));
+                }
+                only_absolute
+            }
+            None => project_absolute.clone(),
+        };
+
+        let mut excludes = opts
+            .exclude
+            .iter()
+            .map(|pattern| {
+                Ok(Exclude {
+                    base: project_absolute.to_path_buf(),
+                    glob: Glob::new(pattern)?,
+                })
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+        if let Some(ignore_paths_file) = &opts.ignore_paths_file {
+            let ignore_paths_file = ignore_paths_file
+                .absolutize_from(&current_dir)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to make path absolute: {ignore_paths_file:?}"))?;
+            excludes.extend(glob::read_ignore_file(&ignore_paths_file)?);
+        }
+
+        let mut excluded_codes = opts
+            .preset
+            .into_iter()
+            .flat_map(|preset| preset.rules().exclude)
+            .filter(|code| {
+                !opts
+                    .severity_overrides
+                    .iter()
+                    .any(|severity_override| severity_override.code == **code)
+            })
+            .map(|code| (*code).to_owned())
+            .chain(opts.exclude_code.iter().cloned())
+            .collect::<HashSet<_>>();
+        if let Some(exclude_code_from) = &opts.exclude_code_from {
+            let exclude_code_from = exclude_code_from
+                .absolutize_from(&current_dir)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to make path absolute: {exclude_code_from:?}"))?;
+            excluded_codes.extend(read_code_file(&exclude_code_from)?);
+        }
+
+        let luarc_absolute = match &opts.luarc {
+            Some(luarc) => {
+                let luarc_absolute = luarc
+                    .absolutize_from(&current_dir)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to make path absolute: {luarc:?}"))?;
+                if !luarc_absolute.is_file() {
+                    return Err(usage_error(miette!(
+                        help = format!(
+                            "Relative `--luarc` paths are resolved from the current directory, not the project: {}",
+                            luarc_absolute.display()
+                        ),
+                        "lua-language-server configuration file doesn't exist: {luarc:?}"
+                    )));
+                }
+                Some(luarc_absolute)
+            }
+            // The server looks for `.luarc.json` in the directory it checks, so point it at the
+            // project's when checking a narrower path.
+            None if opts.only.is_some() => {
+                Some(Cow::<Path>::Owned(project_absolute.join(".luarc.json")))
+                    .filter(|luarc| luarc.is_file())
+            }
+            None => None,
+        };
+
+        // An override to a shown severity may promote a diagnostic the server would otherwise
+        // leave out, because it isn't known what severity it assigns until it runs.
+        let promotes = opts
+            .preset
+            .into_iter()
+            .flat_map(|preset| preset.rules().severities)
+            .map(|(_, severity)| *severity)
+            .chain(
+                opts.severity_overrides
+                    .iter()
+                    .map(|severity_override| severity_override.severity),
+            )
+            .any(|severity| DiagnosticSeverity::from(severity) <= show);
+        // `show` is at least as permissive as `fail` by now.
+        let checklevel = opts
+            .checklevel
+            .or_else(|| (!promotes).then(|| Severity::from_lsp(show)).flatten())
+            .unwrap_or(Severity::Hint);
+
+        let child_cwd = match &opts.child_cwd {
+            Some(child_cwd) => {
+                let child_cwd_absolute = child_cwd
+                    .absolutize_from(&current_dir)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to make path absolute: {child_cwd:?}"))?;
+                if !child_cwd_absolute.is_dir() {
+                    return Err(usage_error(miette!(
+                        "--child-cwd directory doesn't exist: {child_cwd:?}"
+                    )));
+                }
+                child_cwd_absolute
+            }
+            None if project_absolute.is_dir() => project_absolute.clone(),
+            None => Cow::Borrowed(current_dir.as_path()),
+        };
+
+        let lua_language_server = if opts.docker.is_some() || opts.stdin_json {
+            // The executable is looked up in the container, or not run at all.
+            opts.lua_language_server.clone()
+        } else {
+            resolve_server(opts, &current_dir, opts.install_if_missing)?
+        };
+
+        let project_cache = project_cache_dir(&project_absolute);
+        let logpath = server_dir(
+            &current_dir,
+            opts.luals_logpath.as_deref(),
+            project_cache.as_deref(),
+            "log",
+            "--luals-logpath",
+        )?;
+        let metapath = server_dir(
+            &current_dir,
+            opts.metapath.as_deref(),
+            project_cache.as_deref(),
+            "meta",
+            "--metapath",
+        )?;
+
+        let container = match &opts.docker {
+            Some(image) => {
+                let mut container = Container::new(opts.container_runtime.as_deref(), image)?;
+                container.memory_limit = opts.memory_limit.map(|limit| limit.0);
+                container.mount(&project_absolute, container::PROJECT_MOUNT, true)?;
+                // The server writes its diagnostics file to the log directory.
+                let logpath = logpath.as_deref().ok_or_else(|| {
+                    miette!(
+                        help = "Pass `--luals-logpath`",
+                        "`--docker` needs a directory for lua-language-server's diagnostics file, but there's no cache directory"
+                    )
+                })?;
+                container.mount(logpath, "/lualscheck/log", false)?;
+                if let Some(metapath) = &metapath {
+                    container.mount(metapath, "/lualscheck/meta", false)?;
+                }
+                if let Some(luarc) = luarc_absolute
+                    .as_deref()
+                    .filter(|luarc| !path_starts_with(luarc, &project_absolute))
+                {
+                    container.mount(luarc, "/lualscheck/luarc.json", true)?;
+                }
+                Some(container)
+            }
+            None => None,
+        };
+        // Host paths, translated to where they're mounted for `--docker`.
+        let path_arg = |path: &Path| -> miette::Result<OsString> {
+            match &container {
+                Some(container) => container.to_container(path),
+                None => Ok(path.as_os_str().to_owned()),
+            }
+        };
+
+        let mut cmd = match &container {
+            Some(container) => {
+                let mut cmd = container.command(&lua_language_server, &opts.envs);
+                cmd.current_dir(&current_dir);
+                cmd
+            }
+            None => {
+                let mut cmd = Command::new(&*lua_language_server);
+                cmd.current_dir(&*child_cwd);
+                for key in &opts.env_remove {
+                    cmd.env_remove(key);
+                }
+                for env in &opts.envs {
+                    cmd.env(&env.key, &env.value);
+                }
+                cmd
+            }
+        };
+        process::isolate(&mut cmd);
+        if container.is_none() {
+            process::limit(&mut cmd, process_limits(opts));
+        }
+        if opts.lsp {
+            // The server speaks the Language Server Protocol over stdin and stdout.
+            cmd.stdin(Stdio::piped());
+        } else {
+            // The server is in its own process group, so it can't read from the terminal.
+            cmd.stdin(Stdio::null());
+            cmd.arg("--check")
+                .arg(path_arg(&check_absolute)?)
+                .arg("--checklevel")
+                .arg(checklevel.checklevel());
+            if let Some(threads) = opts.luals_threads {
+                cmd.arg("--num_threads").arg(threads.to_string());
+            }
+        }
+        cmd.stdout(Stdio::piped());
+
+        if opts.silent {
+            cmd.stderr(Stdio::null());
+        } else {
+            // Shown if the server fails, or with `-v`.
+            cmd.stderr(Stdio::piped());
+        }
+
+        if let Some(luarc) = &luarc_absolute {
+            cmd.arg("--configpath").arg(path_arg(luarc)?);
+        }
+
+        if let Some(locale) = &opts.locale {
+            cmd.arg("--locale").arg(locale);
+        }
+
+        if let Some(logpath) = &logpath {
+            cmd.arg("--logpath").arg(path_arg(logpath)?);
+        }
+        if let Some(metapath) = &metapath {
+            cmd.arg("--metapath").arg(path_arg(metapath)?);
+        }
+        cmd.args(&opts.luals_args);
+
+        Ok(Self {
+            cmd,
+            lua_language_server,
+            container,
+            project: project_absolute.into_owned(),
+            check_path: check_absolute.into_owned(),
+            relative_base: relative_base.into_owned(),
+            excludes,
+            excluded_codes,
+            logpath,
+            results_glob: Glob::new(&opts.results_glob)?,
+            show,
+            fail,
+        })
+    }
+
+    fn check(self, opts: &Opts) -> miette::Result<CheckReport> {
+        let Self {
+            mut cmd,
+            lua_language_server,
+            container,
+            project: project_absolute,
+            check_path,
+            relative_base,
+            excludes,
+            excluded_codes,
+            logpath,
+            results_glob,
+            show,
+            fail,
+        } = self;
+
+        match (&container, discover::find_executable(&lua_language_server)) {
+            _ if opts.stdin_json => {}
+            (Some(container), _) => log::info!(
+                "lua-language-server: {} in {} (with {})",
+                lua_language_server.display(),
+                container.image(),
+                container.runtime().display()
+            ),
+            (None, Some(path)) => log::info!("lua-language-server: {}", path.display()),
+            (None, None) => log::info!(
+                "lua-language-server: {} (not found)",
+                opts.lua_language_server.display()
+            ),
+        }
+
+        let server_version = if opts.stdin_json {
+            None
+        } else if !opts.lsp
+            || opts.min_luals_version.is_some()
+            || opts.summary_json.is_some()
+            || log::log_enabled!(log::Level::Info)
+        {
+            server_version(match &container {
+                Some(container) => container.version_command(&lua_language_server),
+                None => Command::new(&lua_language_server),
+            })
+        } else {
+            None
+        };
+        if let Some(version) = &server_version {
+            log::info!("lua-language-server version: {version}");
+        }
+        if let Some(required) = opts.min_luals_version.filter(|_| !opts.stdin_json) {
+            check_min_version(
+                opts,
+                &lua_language_server,
+                server_version.as_deref(),
+                required,
+            )?;
+        }
+
+        log::info!("project: {}", project_absolute.display());
+
+        let mut phases = Phases::new();
+        // To find what this run logs.
+        let log_snapshot = logpath
+            .as_deref()
+            .map(server_log::Snapshot::new)
+            .unwrap_or_default();
+
+        // Later overrides take precedence, and `--severity` takes precedence over `--preset`.
+        let overrides = opts
+            .preset
+            .into_iter()
+            .flat_map(|preset| preset.rules().severities)
+            .map(|(code, severity)| (*code, *severity))
+            .chain(opts.severity_overrides.iter().map(|severity_override| {
+                (severity_override.code.as_str(), severity_override.severity)
+            }))
+            .collect::<HashMap<_, _>>();
+
+        // Skip what won't be displayed while reading the diagnostics, rather than holding
+        // them all in memory.
+        let keep_file = |uri: &str| {
+            let url = Url::parse(uri).ok();
+            let url = match &container {
+                Some(container) => url
+                    .as_ref()
+                    .and_then(|url| container.uri_to_host(url))
+                    .or(url),
+                None => url,
+            };
+            let Some(path) = url.and_then(|url| url.to_file_path().ok()) else {
+                // Reported while filtering.
+                return true;
+            };
+            if !path_starts_with(&path, &project_absolute) {
+                log::info!("Ignoring diagnostics in out-of-project path {path:?}");
+                return false;
+            }
+            if let Some(exclude) = excludes.iter().find(|exclude| exclude.is_match(&path)) {
+                log::info!(
+                    "Ignoring diagnostics in path {path:?} excluded by {}",
+                    exclude.glob
+                );
+                return false;
+            }
+            true
+        };
+        let keep_diagnostic = |diagnostic: &Diagnostic| {
+            let code = diagnostic.code.as_ref().map(code_to_string);
+            if code
+                .as_ref()
+                .is_some_and(|code| excluded_codes.contains(code))
+            {
+                return false;
+            }
+            code.and_then(|code| overrides.get(code.as_str()).copied())
+                .map(DiagnosticSeverity::from)
+                .or(diagnostic.severity)
+                .is_none_or(|severity| severity <= show)
+        };
+        let filter = diagnostics_file::Filter {
+            file: &keep_file,
+            diagnostic: &keep_diagnostic,
+            strict: opts.strict_parse,
+        };
+
+        let server_errors;
+        let server_exit;
+        let files_checked;
+        let (diagnostics_file, mut diagnostics) = if opts.stdin_json {
+            let mut contents = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut contents)
+                .into_diagnostic()
+                .wrap_err("Failed to read diagnostics from stdin")?;
+            phases.end("read");
+            server_errors = Vec::new();
+            server_exit = None;
+            files_checked = None;
+            (
+                None,
+                diagnostics_file::parse(contents, "stdin", &filter, None)?,
+            )
+        } else if opts.lsp {
+            let is_failing = |uri: &Url, diagnostic: &Diagnostic| {
+                if !opts.fail_fast_server {
+                    return false;
+                }
+                let included = uri.to_file_path().is_ok_and(|path| {
+                    path_starts_with(&path, &project_absolute)
+                        && !excludes.iter().any(|exclude| exclude.is_match(&path))
+                });
+                let code = diagnostic.code.as_ref().map(code_to_string);
+                if code
+                    .as_ref()
+                    .is_some_and(|code| excluded_codes.contains(code))
+                {
+                    return false;
+                }
+                let severity = code
+                    .and_then(|code| overrides.get(code.as_str()).copied())
+                    .map(DiagnosticSeverity::from)
+                    .or(diagnostic.severity);
+                included
+                    && severity.is_some_and(|severity| severity <= fail)
+                    && (!opts.fixable_only || is_fixable(diagnostic))
+                    && has_tags(opts, diagnostic)
+            };
+            let (diagnostics, files_opened) = run_lsp(
+                &mut cmd,
+                opts,
+                &lua_language_server,
+                &project_absolute,
+                &check_path,
+                &excludes,
+                logpath.as_deref(),
+                &mut phases,
+                is_failing,
+            )?;
+            server_errors = scan_server_log(logpath.as_deref(), &log_snapshot);
+            server_exit = None;
+            files_checked = Some(files_opened);
+            (None, diagnostics)
+        } else {
+            // Newer servers write their diagnostics where they're told. Older ones pick a path
+            // in their log directory and name it on the last line of their output.
+            let mut out_dir = None;
+            if server_version
+                .as_deref()
+                .and_then(Version::find)
+                .is_some_and(|version| version >= CHECK_OUT_PATH_VERSION)
+            {
+                // Only the log directory is mounted writable in the container.
+                let parent = match &container {
+                    Some(_) => logpath
+                        .clone()
+                        .expect("`--docker` always has a log directory"),
+                    None => std::env::temp_dir(),
+                };
+                let mut dir = TempDir::new(&parent)?;
+                if opts.keep_output == Some(None) {
+                    dir.keep();
+                }
+                let out_path = dir.path().join("check.json");
+                let out_arg = match &container {
+                    Some(container) => container.to_container(&out_path)?,
+                    None => out_path.clone().into_os_string(),
+                };
+                cmd.arg("--check_out_path").arg(out_arg);
+                out_dir = Some((dir, out_path));
+            }
+            let out_path = out_dir.as_ref().map(|(_, out_path)| out_path.as_path());
+
+            let started = SystemTime::now();
+            let mut attempt = 1;
+            let ServerRun {
+                exit_code,
+                stdout,
+                stderr,
+                diagnostics_path,
+            } = loop {
+                let run = run_server(
+                    &mut cmd,
+                    opts,
+                    &lua_language_server,
+                    container.as_ref(),
+                    logpath.as_deref(),
+                    out_path,
+                    &mut phases,
+                )?;
+                if run.exit_code.success() {
+                    break run;
+                }
+                // Some versions exit nonzero when they find problems, or fail while shutting down
+                // after checking, so only fail if there's nothing to report.
+                let has_results = run
+                    .diagnostics_path
+                    .as_ref()
+                    .is_some_and(|path| path.is_file())
+                    || reports_no_problems(&run.stdout)
+                    || logpath.as_deref().is_some_and(|logpath| {
+                        find_results_file(logpath, &results_glob, started).is_some()
+                    });
+                if has_results {
+                    log::info!(
+                        "lua-language-server exited unsuccessfully ({}) but wrote its results; reading them",
+                        run.exit_code
+                    );
+                    break run;
+                }
+                let (exit, hint) = describe_failure(opts, run.exit_code, &run.stderr);
+                if attempt > opts.retries {
+                    let message = if attempt > 1 {
+                        format!("lua-language-server failed after {attempt} attempts: {exit}")
+                    } else {
+                        format!("lua-language-server failed: {exit}")
+                    };
+                    return Err(server_failure(
+                        message,
+                        hint.as_deref(),
+                        logpath.as_deref(),
+                        &run.stderr,
+                    ));
+                }
+                let backoff = Duration::from_secs(attempt.into());
+                log::warn!(
+                    "lua-language-server failed ({exit}); retrying in {}s (attempt {} of {})",
+                    backoff.as_secs(),
+                    attempt + 1,
+                    opts.retries + 1
+                );
+                std::thread::sleep(backoff);
+                phases.end("retry");
+                attempt += 1;
+            };
+            server_errors = scan_server_log(logpath.as_deref(), &log_snapshot);
+            files_checked = progress::files_checked(&stdout);
+            let (exit, exit_hint) = describe_failure(opts, exit_code, &stderr);
+            server_exit = (!exit_code.success()).then_some(exit);
+
+            if !stderr.trim().is_empty() {
+                log::info!("lua-language-server stderr:\n{}", stderr.trim_end());
+            }
+
+            let diagnostics_path = match diagnostics_path {
+                Some(diagnostics_path) => Some(diagnostics_path),
+                None if reports_no_problems(&stdout) => None,
+                None => {
+                    let found = logpath
+                        .as_deref()
+                        .and_then(|logpath| find_results_file(logpath, &results_glob, started));
+                    if let Some(found) = &found {
+                        log::info!(
+                            "lua-language-server's output doesn't name a diagnostics file, but it wrote {}",
+                            found.display()
+                        );
+                    }
+                    found
+                }
+            };
+
+            let Some(diagnostics_path) = diagnostics_path else {
+                let Some(last_line) = output_lines(&stdout).last().copied() else {
+                    let searched = match &logpath {
+                        Some(logpath) => format!(
+                            "no file matching `{results_glob}` was written to {}",
+                            logpath.display()
+                        ),
+                        None => "there's no log directory to search, so pass `--luals-logpath`"
+                            .to_owned(),
+                    };
+                    return Err(miette!(
+                        help = format!(
+                            "lua-language-server didn't write any output naming a diagnostics file, and {searched}"
+                        ),
+                        "Couldn't find lua-language-server's diagnostics file"
+                    ));
+                };
+                if reports_no_problems(&stdout) {
+                    log::info!("lua-language-server found no problems: {last_line:?}");
+                } else {
+                    log::info!("No diagnostics file in lua-language-server output: {last_line:?}");
+                }
+                phases.end("parse");
+                return Ok(CheckReport {
+                    files: Vec::new(),
+                    counts: SeverityCounts::default(),
+                    failing: 0,
+                    over_budget: Vec::new(),
+                    bailed: false,
+                    relative_base,
+                    diagnostics_file: None,
+                    server_version,
+                    server_errors,
+                    server_exit,
+                    files_checked,
+                    phases,
+                });
+            };
+
+            let path = diagnostics_path.as_path();
+
+            log::info!("diagnostics file: {}", path.display());
+
+            if !path.exists() {
+                return Err(miette!(
+                    "lua-language-server diagnostics file doesn't exist: {path:?}"
+                ));
+            }
+
+            if let Some(Some(keep_output)) = &opts.keep_output {
+                std::fs::copy(path, keep_output)
+                    .into_diagnostic()
+                    .wrap_err_with(|| {
+                        format!("Failed to write diagnostics file copy: {keep_output:?}")
+                    })?;
+            }
+            let mut diagnostics = diagnostics_file::read(path, &filter, server_version.as_deref())
+                .map_err(|err| match &server_exit {
+                    // Without usable results, the server failing is the real problem.
+                    Some(exit) => {
+                        let hint = exit_hint
+                            .iter()
+                            .cloned()
+                            .chain([format!("Its results couldn't be read: {err}")])
+                            .collect::<Vec<_>>()
+                            .join("\n\n");
+                        server_failure(
+                            format!("lua-language-server failed: {exit}"),
+                            Some(&hint),
+                            logpath.as_deref(),
+                            &stderr,
+                        )
+                    }
+                    None => err,
+                })?;
+            if let Some(container) = &container {
+                diagnostics = container.diagnostics_to_host(diagnostics);
+            }
+            (Some(diagnostics_path), diagnostics)
+        };
+
+        if !overrides.is_empty() {
+            for diagnostic in diagnostics.values_mut().flatten() {
+                let Some(code) = diagnostic.code.as_ref().map(code_to_string) else {
+                    continue;
+                };
+                if let Some(severity) = overrides.get(code.as_str()) {
+                    diagnostic.severity = Some((*severity).into());
+                }
+            }
+        }
+
+        if !excluded_codes.is_empty() {
+            for diagnostics in diagnostics.values_mut() {
+                diagnostics.retain(|diagnostic| {
+                    !diagnostic
+                        .code
+                        .as_ref()
+                        .is_some_and(|code| excluded_codes.contains(&code_to_string(code)))
+                });
+            }
+        }
+
+        if opts.normalize_ranges {
+            for (uri, diagnostics) in &mut diagnostics {
+                if let Some(path) = Url::parse(uri).ok().and_then(|url| url.to_file_path().ok()) {
+                    normalize_ranges(&path, diagnostics);
+                }
+            }
+        }
+
+        phases.end("parse");
+
+        let render = RenderOpts::new(opts);
+
+        let mut seen_diagnostics = HashSet::new();
+
+        let mut found_diagnostics = 0;
+
+        // Files with more failing diagnostics than `--max-per-file`, and how many they have.
+        let mut over_budget = Vec::new();
+
+        let mut files = Vec::new();
+
+        // Set when `--bail` finds a failing diagnostic.
+        let mut bailed = false;
+
+        for (path, diagnostics) in &diagnostics {
+            let mut span = trace::Span::new("filter");
+            span.arg("uri", path.as_str());
+            span.arg("diagnostics", diagnostics.len());
+
+            let url = lsp_types::Url::parse(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to parse URL: {path:?}"))?;
+
+            let relative_path = to_relative_path(&url, &relative_base)?;
+
+            let file_path = url.to_file_path().ok();
+
+            if !file_path
+                .as_ref()
+                .map(|p| path_starts_with(p, &project_absolute))
+                .unwrap_or(true)
+            {
+                log::info!("Ignoring diagnostics in out-of-project path {relative_path:?}");
+                span.arg("skipped", "out of project");
+                continue;
+            }
+
+            if let Some(exclude) = file_path
+                .as_ref()
+                .and_then(|p| excludes.iter().find(|exclude| exclude.is_match(p)))
+            {
+                log::info!(
+                    "Ignoring diagnostics in path {relative_path:?} excluded by {}",
+                    exclude.glob
+                );
+                span.arg("skipped", format!("excluded by {}", exclude.glob));
+                continue;
+            }
+
+            let mut file = FileDiagnostics {
+                path: relative_path,
+                diagnostics: Vec::new(),
+            };
+            let mut file_failing = 0;
+            let mut below_show = 0;
+            let mut not_fixable = 0;
+            let mut untagged = 0;
+            let mut duplicates = 0;
+
+            for diagnostic in by_position(diagnostics) {
+                if diagnostic
+                    .severity
+                    .map(|severity| severity > show)
+                    .unwrap_or(false)
+                {
+                    below_show += 1;
+                    continue;
+                }
+
+                if opts.fixable_only && !is_fixable(diagnostic) {
+                    not_fixable += 1;
+                    continue;
+                }
+
+                if !has_tags(opts, diagnostic) {
+                    untagged += 1;
+                    continue;
+                }
+
+                let path_diagnostic = PathDiagnostic {
+                    cwd: &relative_base,
+                    path: &file.path,
+                    diagnostic,
+                    render: &render,
+                };
+                if !seen_diagnostics.insert(path_diagnostic.to_string()) {
+                    // Don't print duplicate diagnostics.
+                    duplicates += 1;
+                    continue;
+                }
+
+                if diagnostic
+                    .severity
+                    .map(|severity| severity <= fail)
+                    .unwrap_or(false)
+                {
+                    file_failing += 1;
+                    if opts.bail {
+                        file.diagnostics = vec![diagnostic.clone()];
+                        bailed = true;
+                        break;
+                    }
+                }
+
+                file.diagnostics.push(diagnostic.clone());
+            }
+
+            match opts.max_per_file {
+                Some(max) => {
+                    if file_failing > max {
+                        found_diagnostics += file_failing - max;
+                        over_budget.push((file.path.clone(), file_failing));
+                    }
+                }
+                None => found_diagnostics += file_failing,
+            }
+
+            span.arg("path", file.path.to_string_lossy());
+            span.arg("below_show", below_show);
+            span.arg("not_fixable", not_fixable);
+            span.arg("untagged", untagged);
+            span.arg("duplicates", duplicates);
+            span.arg("shown", file.diagnostics.len());
+            span.arg("failing", file_failing);
+
+            if bailed {
+                log::info!("Stopping at the first failing diagnostic for `--bail`");
+                files = vec![file];
+                break;
+            }
+
+            if !file.diagnostics.is_empty() {
+                files.push(file);
+            }
+        }
+
+        if let Some(sort_files) = opts.sort_files {
+            sort_files_by(&mut files, sort_files, &relative_base);
+        }
+
+        let mut counts = SeverityCounts::default();
+        for diagnostic in files.iter().flat_map(|file| &file.diagnostics) {
+            counts.add(diagnostic.severity);
+        }
+
+        Ok(CheckReport {
+            files,
+            counts,
+            failing: found_diagnostics,
+            over_budget,
+            bailed,
+            relative_base,
+            diagnostics_file,
+            server_version,
+            server_errors,
+            server_exit,
+            files_checked,
+            phases,
+        })
+    }
+}
+
+/// Write diagnostics in the default human-readable format.
+fn write_human(
+    out: &mut dyn Write,
+    opts: &Opts,
+    render: &RenderOpts,
+    cwd: &Path,
+    files: &[FileDiagnostics],
+) -> miette::Result<()> {
+    let duplicates = if opts.aggregate_duplicates {
+        index_duplicates(files)
+    } else {
+        HashMap::new()
+    };
+
+    for (file_index, file) in files.iter().enumerate() {
+        let mut code_counts = HashMap::<String, usize>::new();
+        let mut collapsed = BTreeMap::<String, Vec<u32>>::new();
+
+        for (diagnostic_index, diagnostic) in file.diagnostics.iter().enumerate() {
+            let mut others: &[(usize, usize)] = &[];
+            if let Some(locations) = duplicates.get(&DuplicateKey::new(diagnostic)) {
+                if locations[0] != (file_index, diagnostic_index) {
+                    // Rendered alongside the first occurrence.
+                    continue;
+                }
+                others = &locations[1..];
+            }
+
+            // Don't collapse aggregated diagnostics; that would hide their other locations.
+            if opts.collapse > 0 && others.is_empty() {
+                if let Some(code) = &diagnostic.code {
+                    let code = code_to_string(code);
+                    let count = code_counts.entry(code.clone()).or_default();
+                    *count += 1;
+                    if *count > opts.collapse {
+                        collapsed
+                            .entry(code)
+                            .or_default()
+                            .push(diagnostic.range.start.line + 1);
+                        continue;
+                    }
+                }
+            }
+
+            let path_diagnostic = PathDiagnostic {
+                cwd,
+                path: &file.path,
+                diagnostic,
+                render,
+            };
+            write!(out, "\n{path_diagnostic}").into_diagnostic()?;
+
+            if !others.is_empty() {
+                write!(
+                    out,
+                    "{}",
+                    DuplicateLocations {
+                        files,
+                        locations: others,
+                    }
+                )
+                .into_diagnostic()?;
+            }
+        }
+
+        for (code, lines) in &collapsed {
+            write!(out, "\n{}", CollapsedDiagnostics { code, lines }).into_diagnostic()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts of diagnostics by severity.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SeverityCounts {
+    pub error: usize,
+    pub warning: usize,
+    pub info: usize,
+    pub hint: usize,
+}
+
+impl SeverityCounts {
+    fn add(&mut self, severity: Option<DiagnosticSeverity>) {
+        match severity.and_then(Severity::from_lsp) {
+            Some(Severity::Error) => self.error += 1,
+            Some(Severity::Warning) => self.warning += 1,
+            Some(Severity::Information) => self.info += 1,
+            Some(Severity::Hint) => self.hint += 1,
+            None => {}
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.error + self.warning + self.info + self.hint
+    }
+
+    fn most_severe(&self) -> Option<Severity> {
+        [
+            (self.error, Severity::Error),
+            (self.warning, Severity::Warning),
+            (self.info, Severity::Information),
+            (self.hint, Severity::Hint),
+        ]
+        .into_iter()
+        .find_map(|(count, severity)| (count > 0).then_some(severity))
+    }
+}
+
+impl Display for SeverityCounts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut first = true;
+        for (count, singular, plural) in [
+            (self.error, "error", "errors"),
+            (self.warning, "warning", "warnings"),
+            (self.info, "info", "info"),
+            (self.hint, "hint", "hints"),
+        ] {
+            if count == 0 {
+                continue;
+            }
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+            write!(f, "{count} {}", if count == 1 { singular } else { plural })?;
+        }
+        Ok(())
+    }
+}
+
+/// Count diagnostics at or greater than the `fail` severity for `--count-by`, formatted like
+/// `error=3 warning=12`.
+fn count_failing(files: &[FileDiagnostics], fail: DiagnosticSeverity, count_by: CountBy) -> String {
+    let failing = files
+        .iter()
+        .flat_map(|file| &file.diagnostics)
+        .filter(|diagnostic| {
+            diagnostic
+                .severity
+                .map(|severity| severity <= fail)
+                .unwrap_or(false)
+        });
+
+    let counts = match count_by {
+        CountBy::Severity => {
+            let mut counts = SeverityCounts::default();
+            for diagnostic in failing {
+                counts.add(diagnostic.severity);
+            }
+            [
+                (Severity::Error, counts.error),
+                (Severity::Warning, counts.warning),
+                (Severity::Information, counts.info),
+                (Severity::Hint, counts.hint),
+            ]
+            .into_iter()
+            .filter(|(severity, _)| DiagnosticSeverity::from(*severity) <= fail)
+            .map(|(severity, count)| (severity.to_string(), count))
+            .collect::<Vec<_>>()
+        }
+        CountBy::Code => {
+            let mut counts = HashMap::<String, usize>::new();
+            for diagnostic in failing {
+                let code = diagnostic
+                    .code
+                    .as_ref()
+                    .map(code_to_string)
+                    .unwrap_or_else(|| "none".to_owned());
+                *counts.entry(code).or_default() += 1;
+            }
+            let mut counts = counts.into_iter().collect::<Vec<_>>();
+            counts.sort_by(|(code_a, count_a), (code_b, count_b)| {
+                count_b.cmp(count_a).then_with(|| code_a.cmp(code_b))
+            });
+            counts
+        }
+    };
+
+    counts
+        .iter()
+        .map(|(key, count)| format!("{key}={count}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A file's entry in the `--top` report.
+#[derive(Debug, Serialize)]
+struct FileRank<'a> {
+    path: &'a Path,
+    total: usize,
+    #[serde(flatten)]
+    counts: SeverityCounts,
+    /// The most common diagnostic code in the file.
+    code: Option<String>,
+    #[serde(skip)]
+    worst: DiagnosticSeverity,
+}
+
+/// Rank files by their number of diagnostics at or greater than the `fail` severity.
+///
+/// Ties are broken by the worst severity in each file, then by path.
+fn rank_files<'a>(
+    files: &'a [FileDiagnostics],
+    fail: DiagnosticSeverity,
+    top: usize,
+) -> Vec<FileRank<'a>> {
+    let mut ranking = Vec::new();
+    for file in files {
+        let mut counts = SeverityCounts::default();
+        let mut code_counts = HashMap::<String, usize>::new();
+        let mut worst = DiagnosticSeverity::HINT;
+        for diagnostic in &file.diagnostics {
+            let Some(severity) = diagnostic.severity.filter(|severity| *severity <= fail) else {
+                continue;
+            };
+            counts.add(Some(severity));
+            worst = worst.min(severity);
+            if let Some(code) = &diagnostic.code {
+                *code_counts.entry(code_to_string(code)).or_default() += 1;
+            }
+        }
+
+        let total = counts.total();
+        if total == 0 {
+            continue;
+        }
+
+        let code = code_counts
+            .into_iter()
+            .max_by(|(code_a, count_a), (code_b, count_b)| {
+                count_a.cmp(count_b).then_with(|| code_b.cmp(code_a))
+            })
+            .map(|(code, _)| code);
+
+        ranking.push(FileRank {
+            path: &file.path,
+            total,
+            counts,
+            code,
+            worst,
+        });
+    }
+
+    ranking.sort_by(|a, b| {
+        b.total
+            .cmp(&a.total)
+            .then_with(|| a.worst.cmp(&b.worst))
+            .then_with(|| a.path.cmp(b.path))
+    });
+    ranking.truncate(top);
+    ranking
+}
+
+/// A directory's entry in the `--summary-by` report.
+#[derive(Debug, Serialize)]
+struct DirectorySummary {
+    path: PathBuf,
+    #[serde(flatten)]
+    counts: SeverityCounts,
+    /// The most common code among the directory's most severe diagnostics.
+    worst_code: Option<String>,
+}
+
+/// Group diagnostic counts by the first `depth` directories of each file's path.
+///
+/// Files at the top level of the project are grouped under `.`.
+fn summarize_directories(files: &[FileDiagnostics], depth: usize) -> Vec<DirectorySummary> {
+    // Directory -> (counts, worst severity, code counts at the worst severity).
+    let mut directories = BTreeMap::<
+        PathBuf,
+        (
+            SeverityCounts,
+            Option<DiagnosticSeverity>,
+            HashMap<String, usize>,
+        ),
+    >::new();
+    for file in files {
+        let mut directory = file
+            .path
+            .parent()
+            .into_iter()
+            .flat_map(|parent| parent.components())
+            .take(depth)
+            .collect::<PathBuf>();
+        if directory.as_os_str().is_empty() {
+            directory = PathBuf::from(".");
+        }
+        let (counts, worst, codes) = directories.entry(directory).or_default();
+        for diagnostic in &file.diagnostics {
+            counts.add(diagnostic.severity);
+            let Some(severity) = diagnostic.severity else {
+                continue;
+            };
+            if worst.map(|worst| severity < worst).unwrap_or(true) {
+                *worst = Some(severity);
+                codes.clear();
+            }
+            if *worst == Some(severity) {
+                if let Some(code) = &diagnostic.code {
+                    *codes.entry(code_to_string(code)).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    directories
+        .into_iter()
+        .map(|(path, (counts, _, codes))| DirectorySummary {
+            path,
+            counts,
+            worst_code: codes
+                .into_iter()
+                .max_by(|(code_a, count_a), (code_b, count_b)| {
+                    count_a.cmp(count_b).then_with(|| code_b.cmp(code_a))
+                })
+                .map(|(code, _)| code),
+        })
+        .collect()
+}
+
+/// Human-readable `--summary-by` report.
+struct DirectoryTable<'a>(&'a [DirectorySummary]);
+
+impl<'a> Display for DirectoryTable<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let header = "directory";
+        let width = self
+            .0
+            .iter()
+            .map(|summary| summary.path.display().to_string().len())
+            .chain([header.len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:width$}  {:>6}  {:>8}  {:>6}  {:>6}  worst code",
+            header.if_supports_color(Stdout, |text| text.bold()),
+            "errors",
+            "warnings",
+            "info",
+            "hints",
+        )?;
+        for summary in self.0 {
+            writeln!(
+                f,
+                "{:width$}  {:>6}  {:>8}  {:>6}  {:>6}  {}",
+                summary.path.display().to_string(),
+                summary.counts.error,
+                summary.counts.warning,
+                summary.counts.info,
+                summary.counts.hint,
+                summary.worst_code.as_deref().unwrap_or_default(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Human-readable `--top` report.
+struct FileRanking<'a>(&'a [FileRank<'a>]);
+
+impl<'a> Display for FileRanking<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for rank in self.0 {
+            write!(
+                f,
+                "{:>6}  {}  {}",
+                rank.total,
+                rank.path
+                    .display()
+                    .if_supports_color(Stdout, |text| text.bold()),
+                rank.counts
+            )?;
+            if let Some(code) = &rank.code {
+                write!(f, "; mostly {code}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a `--diff` report in the human-readable format.
+fn write_diff_human(
+    out: &mut dyn Write,
+    render: &RenderOpts,
+    cwd: &Path,
+    diff: &Diff,
+) -> miette::Result<()> {
+    writeln!(out, "\nNew problems ({}):", diff.new.len()).into_diagnostic()?;
+    for (path, diagnostic) in &diff.new {
+        let path_diagnostic = PathDiagnostic {
+            cwd,
+            path,
+            diagnostic,
+            render,
+        };
+        write!(out, "\n{path_diagnostic}").into_diagnostic()?;
+    }
+
+    writeln!(out, "\nFixed problems ({}):", diff.fixed.len()).into_diagnostic()?;
+    for diagnostic in &diff.fixed {
+        write!(out, "{}", CompactDiagnostic(diagnostic)).into_diagnostic()?;
+    }
+
+    writeln!(out, "\nUnchanged problems ({}):", diff.unchanged.len()).into_diagnostic()?;
+    for (path, diagnostic) in &diff.unchanged {
+        write!(
+            out,
+            "{}",
+            CompactDiagnostic(&JsonDiagnostic::new(path, diagnostic, None))
+        )
+        .into_diagnostic()?;
+    }
+    Ok(())
+}
+
+/// Start the server and record it for the interrupt handler.
+fn spawn_server(
+    cmd: &mut Command,
+    opts: &Opts,
+    lua_language_server: &Path,
+    container: Option<&Container>,
+) -> miette::Result<Child> {
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && container.is_none() => {
+            return Err(discover::server_not_found(opts, lua_language_server));
+        }
+        Err(err)
+            if err.kind() == std::io::ErrorKind::PermissionDenied
+                && opts.nice.is_some_and(|nice| nice < 0) =>
+        {
+            return Err(miette!(
+                help = "Only privileged users can raise a process's priority",
+                "Failed to run lua-language-server at `--nice {}`: {err}",
+                opts.nice.unwrap_or_default()
+            ));
+        }
+        Err(err) => {
+            return Err(err).into_diagnostic().wrap_err_with(|| {
+                format!(
+                    "Failed to run lua-language-server: {}",
+                    lua_language_server.display()
+                )
+            })
+        }
+    };
+    process::track(&child);
+    if let Some(container) = container {
+        process::track_container(container.runtime(), container.name());
+    } else if let Err(err) = process::limit_child(&child, process_limits(opts)) {
+        process::kill_tree(&mut child).into_diagnostic()?;
+        process::untrack();
+        return Err(err)
+            .into_diagnostic()
+            .wrap_err("Failed to limit lua-language-server's memory");
+    }
+    Ok(child)
+}
+
+/// The `--nice` and `--memory-limit` limits for the server.
+fn process_limits(opts: &Opts) -> process::Limits {
+    process::Limits {
+        nice: opts.nice,
+        memory: opts.memory_limit.map(|limit| limit.0),
+    }
+}
+
+/// Describe how the server exited unsuccessfully, with a hint, blaming `--memory-limit` if
+/// it was probably hit.
+fn describe_failure(opts: &Opts, exit_code: ExitStatus, stderr: &str) -> (String, Option<String>) {
+    let (exit, hint) = process::describe_exit(exit_code);
+    let Some(limit) = opts.memory_limit else {
+        return (exit, hint.map(str::to_owned));
+    };
+    let stderr = stderr.to_ascii_lowercase();
+    // Failed allocations usually crash the server or make Lua raise "not enough memory".
+    // Container runtimes exit with 137 when the container is killed for using too much.
+    let out_of_memory = ["not enough memory", "out of memory", "bad_alloc"]
+        .iter()
+        .any(|message| stderr.contains(message))
+        || exit_code.code().is_none()
+        || (opts.docker.is_some() && exit_code.code() == Some(137))
+        // `STATUS_NO_MEMORY`.
+        || (cfg!(windows) && exit_code.code() == Some(0xC000_0017_u32 as i32));
+    if out_of_memory {
+        (
+            format!("{exit}, probably from exceeding `--memory-limit {limit}`"),
+            Some(format!(
+                "lua-language-server probably ran out of memory under `--memory-limit {limit}`; raise the limit, or check less of the project with `--only`"
+            )),
+        )
+    } else {
+        (exit, hint.map(str::to_owned))
+    }
+}
+
+/// Run the server as a language server for `--lsp`, opening the Lua files under `check_path`
+/// and collecting the diagnostics it publishes for them, along with how many files it opened.
+///
+/// The server is killed as soon as `is_failing` matches one of its diagnostics, for
+/// `--fail-fast-server`.
+#[allow(clippy::too_many_arguments)]
+fn run_lsp(
+    cmd: &mut Command,
+    opts: &Opts,
+    lua_language_server: &Path,
+    project: &Path,
+    check_path: &Path,
+    excludes: &[Exclude],
+    logpath: Option<&Path>,
+    phases: &mut Phases,
+    is_failing: impl Fn(&Url, &Diagnostic) -> bool,
+) -> miette::Result<(BTreeMap<String, Vec<Diagnostic>>, usize)> {
+    let files = lsp::lua_files(check_path, &|path| {
+        excludes.iter().any(|exclude| exclude.is_match(path))
+    })?;
+    log::info!("Opening {} files", files.len());
+
+    let mut child = spawn_server(cmd, opts, lua_language_server, None)?;
+    phases.end("spawn");
+
+    let stderr_handle = child.stderr.take().map(process::read_tail);
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(miette!(
+            "lua-language-server process doesn't have stdin and stdout handles"
+        ));
+    };
+    let mut client = lsp::Client::new(stdin, stdout);
+
+    let checking = format!("Checking {}…", opts.project.display());
+    let spinner = (!opts.silent).then(|| Spinner::start(checking.clone()));
+    let deadline = opts.timeout.map(|timeout| Instant::now() + *timeout);
+    let outcome = client.collect(project, &files, deadline, |uri, diagnostics| {
+        diagnostics
+            .iter()
+            .any(|diagnostic| is_failing(uri, diagnostic))
+    });
+    let server_elapsed = phases.end(Phases::CHECK);
+    if let Some(spinner) = spinner {
+        spinner.stop();
+    }
+
+    let outcome = match outcome {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            process::kill_tree(&mut child).into_diagnostic()?;
+            process::untrack();
+            return Err(err);
+        }
+    };
+    match outcome {
+        lsp::Outcome::Settled => {
+            if let Err(err) = client.shutdown() {
+                log::debug!("Failed to shut down lua-language-server: {err}");
+            }
+            if process::wait_timeout(&mut child, lsp::SHUTDOWN_TIME)
+                .into_diagnostic()?
+                .is_none()
+            {
+                log::debug!("lua-language-server didn't exit after `shutdown`; killing it");
+                process::kill_tree(&mut child).into_diagnostic()?;
+            }
+        }
+        lsp::Outcome::Stopped => {
+            log::info!(
+                "Killing lua-language-server after {server_elapsed:?} at the first failing diagnostic"
+            );
+            process::kill_tree(&mut child).into_diagnostic()?;
+        }
+        lsp::Outcome::TimedOut => {
+            log::debug!("Killing lua-language-server after {server_elapsed:?}");
+            process::kill_tree(&mut child).into_diagnostic()?;
+            process::untrack();
+            return Err(server_failure(
+                format!(
+                    "lua-language-server timed out after {}",
+                    opts.timeout.as_ref().expect("Timed out without a timeout")
+                ),
+                None,
+                logpath,
+                &process::join_tail(stderr_handle),
+            ));
+        }
+        lsp::Outcome::Exited => {
+            let exit_code = child.wait().into_diagnostic()?;
+            process::untrack();
+            let stderr = process::join_tail(stderr_handle);
+            let (exit, hint) = describe_failure(opts, exit_code, &stderr);
+            return Err(server_failure(
+                format!("lua-language-server exited before it finished checking: {exit}"),
+                hint.as_deref(),
+                logpath,
+                &stderr,
+            ));
+        }
+    }
+    process::untrack();
+
+    let stderr = process::join_tail(stderr_handle);
+    if !stderr.trim().is_empty() {
+        log::info!("lua-language-server stderr:\n{}", stderr.trim_end());
+    }
+    if !opts.summary_only && !opts.silent {
+        eprintln!("{checking} done in {:.1}s", server_elapsed.as_secs_f64());
+    }
+
+    Ok((client.diagnostics, files.len()))
+}
+
+/// The output of one `lua-language-server --check` run.
+struct ServerRun {
+    exit_code: ExitStatus,
+    stdout: String,
+    stderr: String,
+    /// The diagnostics file the server wrote, as a host path.
+    diagnostics_path: Option<PathBuf>,
+}
+
+/// Run the server once and wait for it to exit, forwarding its output as requested.
+///
+/// The server writes its diagnostics to `out_path` if it's given, and otherwise names the
+/// file it wrote on the last line of its output.
+///
+/// Timing out is an error, rather than a failed run for `--retries` to retry.
+fn run_server(
+    cmd: &mut Command,
+    opts: &Opts,
+    lua_language_server: &Path,
+    container: Option<&Container>,
+    logpath: Option<&Path>,
+    out_path: Option<&Path>,
+    phases: &mut Phases,
+) -> miette::Result<ServerRun> {
+    let mut child = spawn_server(cmd, opts, lua_language_server, container)?;
+    phases.end("spawn");
+
+    let stderr_handle = child.stderr.take().map(process::read_tail);
+
+    let luals_stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| miette!("lua-language-server process doesn't have a stdout handle"))?;
+
+    let luals_output = if opts.summary_only || opts.silent || opts.no_luals_output {
+        LualsOutput::None
+    } else if opts.luals_output == LualsOutput::Stdout
+        && (opts.format != Format::Human || opts.count || opts.count_by.is_some())
+    {
+        // Don't mix the server's output with machine-readable output.
+        LualsOutput::None
+    } else {
+        opts.luals_output
+    };
+
+    let join_handle = std::thread::spawn(move || {
+        let mut progress = (luals_output == LualsOutput::Progress).then(Progress::new);
+        let tail = read_output(luals_stdout, |bytes| {
+            match luals_output {
+                LualsOutput::Stdout => std::io::stdout().write_all(bytes).into_diagnostic()?,
+                LualsOutput::Stderr => std::io::stderr().write_all(bytes).into_diagnostic()?,
+                LualsOutput::Progress => {
+                    if let Some(progress) = &mut progress {
+                        progress.feed(bytes);
+                    }
+                }
+                LualsOutput::None => {}
+            }
+            Ok(())
+        });
+        if let (Ok(_), Some(progress)) = (&tail, &mut progress) {
+            progress.finish();
+        }
+        tail.map(OutputTail::finish)
+    });
+
+    // Let users know we're not hung while the server's output is hidden.
+    let print_progress = (opts.no_luals_output || opts.luals_output == LualsOutput::None)
+        && !opts.summary_only
+        && !opts.silent;
+    let checking = format!("Checking {}…", opts.project.display());
+    // The spinner would interleave with the server's output if it was forwarded.
+    let spinner = (luals_output == LualsOutput::None && !opts.silent)
+        .then(|| Spinner::start(checking.clone()));
+
+    let exit_code = match opts.timeout {
+        Some(timeout) => process::wait_timeout(&mut child, *timeout),
+        None => child.wait().map(Some),
+    };
+    let server_elapsed = phases.end(Phases::CHECK);
+
+    if let Some(spinner) = spinner {
+        spinner.stop();
+    }
+    let exit_code = match exit_code.into_diagnostic()? {
+        Some(exit_code) => {
+            process::untrack();
+            exit_code
+        }
+        None => {
+            log::debug!("Killing lua-language-server after {server_elapsed:?}");
+            if let Some(container) = container {
+                container.kill();
+            }
+            process::kill_tree(&mut child).into_diagnostic()?;
+            process::untrack();
+            // Let the reader finish forwarding whatever the server wrote.
+            if let Ok(Err(err)) = join_handle.join() {
+                log::debug!("Reading lua-language-server output failed: {err}");
+            }
+            return Err(server_failure(
+                format!(
+                    "lua-language-server timed out after {}",
+                    opts.timeout.as_ref().expect("Timed out without a timeout")
+                ),
+                None,
+                logpath,
+                &process::join_tail(stderr_handle),
+            ));
+        }
+    };
+
+    let stderr = process::join_tail(stderr_handle);
+
+    if print_progress {
+        eprintln!("{checking} done in {:.1}s", server_elapsed.as_secs_f64());
+    }
+
+    let (result, dropped) = match join_handle.join() {
+        Ok(result) => result?,
+        Err(panic_value) => {
+            std::panic::resume_unwind(panic_value);
+        }
+    };
+
+    // Only the diagnostics file's path is read from stdout, so invalid UTF-8 elsewhere,
+    // like progress output in an unusual locale or text echoed from a Latin-1 source file,
+    // doesn't matter.
+    if let Err(err) = std::str::from_utf8(&result) {
+        log::warn!(
+            "lua-language-server wrote invalid UTF-8 to stdout at byte {}; ignoring the invalid bytes",
+            dropped + err.valid_up_to()
+        );
+    }
+    let stdout = String::from_utf8_lossy(&result);
+    let diagnostics_path = locate_diagnostics(out_path, &stdout, container);
+
+    Ok(ServerRun {
+        exit_code,
+        stdout: stdout.into_owned(),
+        stderr,
+        diagnostics_path,
+    })
+}
+
+/// The diagnostics file the server wrote, at `out_path` with `--check_out_path` or named in
+/// its `stdout` otherwise.
+fn locate_diagnostics(
+    out_path: Option<&Path>,
+    stdout: &str,
+    container: Option<&Container>,
+) -> Option<PathBuf> {
+    match out_path {
+        // Only written if the check finished.
+        Some(out_path) => Some(out_path.to_owned()).filter(|path| path.is_file()),
+        None => {
+            let to_host = |path: &str| match container {
+                Some(container) => container
+                    .to_host(Path::new(path))
+                    .unwrap_or_else(|| PathBuf::from(path)),
+                None => PathBuf::from(path),
+            };
+            find_diagnostics_path(stdout, |path| to_host(path).is_file()).map(to_host)
+        }
+    }
+}
+
+/// How much of the end of the server's stdout to keep when checking a project.
+///
+/// Only the last lines are needed to find the diagnostics file, and progress bars can redraw
+/// megabytes of output on large projects.
+const STDOUT_CAP: usize = 64 * 1024;
+
+/// The end of the server's stdout, kept as it's read.
+#[derive(Debug, Default)]
+struct OutputTail {
+    /// Up to twice [`STDOUT_CAP`] bytes, since it's trimmed in batches.
+    contents: Vec<u8>,
+    /// How many bytes were dropped from the start.
+    dropped: usize,
+}
+
+impl OutputTail {
+    fn push(&mut self, bytes: &[u8]) {
+        self.contents.extend(bytes);
+        // Trim in batches, rather than on every read.
+        if self.contents.len() > 2 * STDOUT_CAP {
+            self.dropped += trim_output(&mut self.contents);
+        }
+    }
+
+    /// The last [`STDOUT_CAP`] bytes or so, and how many bytes were dropped before them.
+    fn finish(mut self) -> (Vec<u8>, usize) {
+        self.dropped += trim_output(&mut self.contents);
+        (self.contents, self.dropped)
+    }
+}
+
+/// Read the server's stdout to the end, passing everything it writes to `forward` and keeping
+/// only its [`OutputTail`].
+fn read_output(
+    mut reader: impl Read,
+    mut forward: impl FnMut(&[u8]) -> miette::Result<()>,
+) -> miette::Result<OutputTail> {
+    let mut tail = OutputTail::default();
+    let mut buffer = vec![0; 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(tail),
+            Ok(n) => {
+                tail.push(&buffer[..n]);
+                forward(&buffer[..n])?;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err).into_diagnostic(),
+        }
+    }
+}
+
+/// Drop all but about the last [`STDOUT_CAP`] bytes of the server's stdout, starting at a line
+/// break, returning how many bytes were dropped.
+fn trim_output(contents: &mut Vec<u8>) -> usize {
+    if contents.len() <= STDOUT_CAP {
+        return 0;
+    }
+    let mut start = contents.len() - STDOUT_CAP;
+    // Don't keep a partial line, which could be mistaken for a whole one.
+    if let Some(line_break) = contents[start..]
+        .iter()
+        .position(|byte| matches!(byte, b'\n' | b'\r'))
+    {
+        start += line_break + 1;
+    }
+    contents.drain(..start);
+    start
+}
+
+/// How many of the errors the server logged to list.
+const SERVER_ERRORS_SHOWN: usize = 10;
+
+/// Find the errors the server logged since `before` was taken, and warn about them.
+fn scan_server_log(logpath: Option<&Path>, before: &server_log::Snapshot) -> Vec<String> {
+    let Some(logpath) = logpath else {
+        return Vec::new();
+    };
+    let errors = server_log::errors(logpath, before);
+    if !errors.is_empty() {
+        log::warn!(
+            "lua-language-server logged {} errors, so some diagnostics may be missing:\n{}",
+            errors.len(),
+            list_server_errors(&errors, logpath)
+        );
+    }
+    errors
+}
+
+/// The first few errors the server logged, one per line.
+fn list_server_errors(errors: &[String], logpath: &Path) -> String {
+    let mut list = errors
+        .iter()
+        .take(SERVER_ERRORS_SHOWN)
+        .map(|error| format!("  {error}"))
+        .collect::<Vec<_>>();
+    if errors.len() > SERVER_ERRORS_SHOWN {
+        list.push(format!(
+            "  ...and {} more in {}",
+            errors.len() - SERVER_ERRORS_SHOWN,
+            logpath.display()
+        ));
+    }
+    list.join("\n")
+}
+
+/// Fail for `--fail-on-luals-errors` if the server logged errors.
+fn check_server_errors(opts: &Opts, report: &CheckReport) -> miette::Result<()> {
+    if !opts.fail_on_luals_errors || report.server_errors.is_empty() {
+        return Ok(());
+    }
+    Err(miette!(
+        help = "Check lua-language-server's logs, or drop `--fail-on-luals-errors` to only warn",
+        "lua-language-server logged {} errors while checking",
+        report.server_errors.len()
+    ))
+}
+
+/// Warn if the server exited unsuccessfully after writing its results, or fail for
+/// `--strict-luals-exit`.
+fn check_server_exit(opts: &Opts, report: &CheckReport) -> miette::Result<()> {
+    let Some(exit) = &report.server_exit else {
+        return Ok(());
+    };
+    if opts.strict_luals_exit {
+        return Err(miette!(
+            help = "Drop `--strict-luals-exit` to only warn",
+            "lua-language-server exited unsuccessfully after checking: {exit}"
+        ));
+    }
+    log::warn!("lua-language-server exited unsuccessfully after checking: {exit}");
+    Ok(())
+}
+
+/// An error for when the server fails or times out, with a hint, the end of its stderr, and
+/// where to find its logs.
+fn server_failure(
+    message: String,
+    hint: Option<&str>,
+    logpath: Option<&Path>,
+    stderr: &str,
+) -> miette::Report {
+    let mut help = Vec::new();
+    help.extend(hint.map(str::to_owned));
+    let stderr = process::tail_lines(stderr, process::STDERR_TAIL_LINES);
+    if !stderr.is_empty() {
+        help.push(format!(
+            "lua-language-server's stderr ended with:\n{stderr}"
+        ));
+    }
+    if let Some(logpath) = logpath {
+        help.push(format!(
+            "lua-language-server's logs are in {}",
+            logpath.display()
+        ));
+    }
+    if help.is_empty() {
+        miette!("{message}")
+    } else {
+        miette!(help = help.join("\n\n"), "{message}")
+    }
+}
+
+/// The `--summary-json` metadata for a run which got as far as rendering its report.
+fn run_summary(
+    opts: &Opts,
+    server_version: Option<&str>,
+    phases: &Phases,
+    severities: SeverityCounts,
+    failing: usize,
+) -> RunSummary {
+    RunSummary {
+        diagnostics: severities.total(),
+        failing,
+        fail: opts.fail,
+        severities,
+        server_version: server_version.map(ToOwned::to_owned),
+        luals_version: server_version.and_then(Version::find),
+        elapsed_seconds: phases.total().as_secs_f64(),
+        server_seconds: phases.server().as_secs_f64(),
+        error: None,
+    }
+}
+
+/// Write the `--summary-json` file.
+fn write_summary_json(path: &Path, summary: &RunSummary) -> miette::Result<()> {
+    let mut file = BufWriter::new(
+        File::create(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create summary file: {path:?}"))?,
+    );
+    format::write_json(&mut file, summary)?;
+    file.flush().into_diagnostic()
+}
+
+/// Find the `lua-language-server` executable to run, auto-detecting or installing it if it's
+/// the default and isn't in `$PATH`.
+fn resolve_server(
+    opts: &Opts,
+    current_dir: &Path,
+    install_if_missing: bool,
+) -> miette::Result<PathBuf> {
+    // Relative executable paths are ambiguous once the working directory changes.
+    let program = if opts.lua_language_server.components().count() > 1 {
+        let absolute = opts
+            .lua_language_server
+            .absolutize_from(current_dir)
+            .into_diagnostic()
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to make path absolute: {:?}",
+                    opts.lua_language_server
+                )
+            })?;
+        if absolute.is_dir() {
+            // Probably an install root, like an extracted release archive.
+            let executable = discover::server_executables(&absolute)
+                .into_iter()
+                .find(|candidate| candidate.is_file())
+                .ok_or_else(|| {
+                    miette!(
+                        help = "Pass the path to the `lua-language-server` executable itself",
+                        "No lua-language-server executable found in directory {:?}",
+                        opts.lua_language_server
+                    )
+                })?;
+            log::debug!("Found {} in {}", executable.display(), absolute.display());
+            executable
+        } else {
+            absolute.into_owned()
+        }
+    } else if opts.lua_language_server_source == Some(ValueSource::DefaultValue)
+        && (!opts.no_auto_detect || install_if_missing)
+        && discover::find_executable(&opts.lua_language_server).is_none()
+    {
+        // Only the default is auto-detected; explicit names should mean what they say.
+        let detected = if opts.no_auto_detect {
+            None
+        } else {
+            discover::auto_detect()
+        };
+        match detected {
+            Some(path) => path,
+            None if install_if_missing => install::install(opts)?,
+            None => opts.lua_language_server.clone(),
+        }
+    } else {
+        opts.lua_language_server.clone()
+    };
+
+    // Batch files aren't found in `$PATH` like executables, and are run through `cmd.exe`,
+    // which the standard library quotes arguments for. Where possible, run a shim's target
+    // directly instead, avoiding `cmd.exe`'s quoting rules entirely.
+    let program = match discover::find_executable(&program) {
+        Some(found) if cfg!(windows) && discover::is_batch_file(&found) => {
+            match discover::resolve_shim(&found) {
+                Some(target) => {
+                    log::debug!("Resolved {} to {}", found.display(), target.display());
+                    target
+                }
+                None => found,
+            }
+        }
+        _ => program,
+    };
+    Ok(program)
+}
+
+/// Print `--version` information. A missing server is reported rather than failing, since
+/// this is often run to debug exactly that.
+fn print_version(opts: &Opts) -> miette::Result<()> {
+    println!("lualscheck {}", env!("CARGO_PKG_VERSION"));
+    let current_dir = std::env::current_dir().into_diagnostic()?;
+    let program = match resolve_server(opts, &current_dir, false) {
+        Ok(program) => program,
+        Err(err) => {
+            println!("lua-language-server: not found ({err})");
+            return Ok(());
+        }
+    };
+    match discover::find_executable(&program) {
+        Some(path) => {
+            println!("lua-language-server: {}", path.display());
+            match server_version(Command::new(&path)) {
+                Some(version) => println!("lua-language-server version: {version}"),
+                None => println!("lua-language-server version: unknown (`--version` failed)"),
+            }
+        }
+        None => println!("lua-language-server: not found ({})", program.display()),
+    }
+    Ok(())
+}
+
+/// Check `--min-luals-version` against the server's `--version` output.
+fn check_min_version(
+    opts: &Opts,
+    program: &Path,
+    server_version: Option<&str>,
+    required: Version,
+) -> miette::Result<()> {
+    let Some(output) = server_version else {
+        if opts.docker.is_none() && discover::find_executable(program).is_none() {
+            return Err(discover::server_not_found(opts, program));
+        }
+        return Err(miette!(
+            help = "Check that `-c` points to lua-language-server",
+            "`{} --version` failed, so `--min-luals-version {required}` can't be checked",
+            program.display()
+        ));
+    };
+    match Version::find(output) {
+        Some(found) if found >= required => Ok(()),
+        Some(found) => Err(miette!(
+            help = format!(
+                "Upgrade lua-language-server, or install a newer release with \
+                `lualscheck install --luals-version {required}`"
+            ),
+            "lua-language-server {found} is older than the required version {required}: {}",
+            program.display()
+        )),
+        None => Err(miette!(
+            "Couldn't find a version in `{} --version` output, so `--min-luals-version {required}` can't be checked: {output:?}",
+            program.display()
+        )),
+    }
+}
+
+/// Get the server's version from `lua-language-server --version`.
+fn server_version(mut cmd: Command) -> Option<String> {
+    let output = cmd
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+}
+
+/// Wall-clock time spent in each phase of a run, for `--timings` and `--verbose`.
+#[derive(Debug)]
+struct Phases {
+    /// When the last phase ended.
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Phases {
+    /// The phase spent waiting for the server. Everything after this is processing.
+    const CHECK: &'static str = "check";
+
+    fn new() -> Self {
+        Self {
+            last: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Record a phase ending now, returning its duration.
+    fn end(&mut self, name: &'static str) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        trace::complete(name, self.last, Default::default());
+        self.last = now;
+        self.phases.push((name, elapsed));
+        log::info!("{name}: {:.3}s", elapsed.as_secs_f64());
+        elapsed
+    }
+
+    /// The total time spent in all phases.
+    fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, elapsed)| *elapsed).sum()
+    }
+
+    /// The time spent waiting for the server.
+    fn server(&self) -> Duration {
+        self.phases
+            .iter()
+            .filter(|(name, _)| *name == Self::CHECK)
+            .map(|(_, elapsed)| *elapsed)
+            .sum()
+    }
+
+    /// Print the timings requested by `--timings` and `--verbose`.
+    fn print(&self, opts: &Opts) {
+        if opts.verbose > 0 {
+            let summary = self
+                .phases
+                .iter()
+                .map(|(name, elapsed)| format!("{name}: {:.3}s", elapsed.as_secs_f64()))
+                .collect::<Vec<_>>();
+            eprintln!(
+                "total: {:.3}s ({})",
+                self.total().as_secs_f64(),
+                summary.join(", ")
+            );
+        }
+        if opts.timings {
+            let server = self.server();
+            // Retries run the server more than once, and processing starts after the last run.
+            let processing = self
+                .phases
+                .iter()
+                .rposition(|(name, _)| *name == Self::CHECK)
+                .map(|last_check| {
+                    self.phases[last_check + 1..]
+                        .iter()
+                        .map(|(_, elapsed)| *elapsed)
+                        .sum::<Duration>()
+                })
+                .unwrap_or_default();
+            eprintln!(
+                "server: {:.1}s, processing: {:.1}s",
+                server.as_secs_f64(),
+                processing.as_secs_f64()
+            );
+        }
+    }
+}
+
+/// The diagnostics to display for a single file in the project.
+#[derive(Debug, Clone)]
+pub struct FileDiagnostics {
+    /// The file's path, relative to the project root or `--relative-to`.
+    pub path: PathBuf,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Sort files for `--sort-files`, breaking ties by path.
+fn sort_files_by(files: &mut [FileDiagnostics], sort_files: SortFiles, relative_base: &Path) {
+    // Compare paths by their components, so `a/z.lua` sorts before `a/z2.lua` and `a/b/c.lua`
+    // sorts the same way on every platform.
+    let path_key = |file: &FileDiagnostics| {
+        file.path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+            .collect::<Vec<_>>()
+    };
+    match sort_files {
+        SortFiles::Path => files.sort_by_cached_key(path_key),
+        SortFiles::Mtime => files.sort_by_cached_key(|file| {
+            let modified = std::fs::metadata(relative_base.join(&file.path))
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            (std::cmp::Reverse(modified), path_key(file))
+        }),
+        SortFiles::Count => files
+            .sort_by_cached_key(|file| (std::cmp::Reverse(file.diagnostics.len()), path_key(file))),
+    }
+}
+
+/// Check if a diagnostic has one of the `--tags-only` tags, or any diagnostic if there are
+/// none.
+fn has_tags(opts: &Opts, diagnostic: &Diagnostic) -> bool {
+    opts.tags_only.is_empty()
+        || diagnostic.tags.iter().flatten().any(|tag| {
+            opts.tags_only
+                .iter()
+                .any(|tags_only| tags_only.to_lsp() == *tag)
+        })
+}
+
+/// Keys in a diagnostic's `data` which indicate that the server can fix it automatically.
+const FIX_DATA_KEYS: &[&str] = &["codeAction", "codeActions", "edit", "edits", "fix", "fixes"];
+
+/// A file's diagnostics in order of their ranges, so the report doesn't depend on the order
+/// the server wrote them in.
+///
+/// Ties are broken by everything that's displayed, so only identical diagnostics keep their
+/// order.
+fn by_position(diagnostics: &[Diagnostic]) -> Vec<&Diagnostic> {
+    let mut diagnostics = diagnostics.iter().collect::<Vec<_>>();
+    diagnostics.sort_by_cached_key(|diagnostic| {
+        (
+            diagnostic.range.start,
+            diagnostic.range.end,
+            diagnostic.severity,
+            diagnostic.code.as_ref().map(code_to_string),
+            diagnostic.message.clone(),
+            diagnostic.source.clone(),
+        )
+    });
+    diagnostics
+}
+
+/// Check if a diagnostic's `data` indicates an automatic fix is available, for `--fixable-only`.
+fn is_fixable(diagnostic: &Diagnostic) -> bool {
+    match &diagnostic.data {
+        None | Some(Value::Null) => false,
+        Some(Value::Object(data)) => {
+            let fixable = FIX_DATA_KEYS.iter().any(|key| {
+                data.get(*key).is_some_and(|value| match value {
+                    Value::Null | Value::Bool(false) => false,
+                    Value::Array(fixes) => !fixes.is_empty(),
+                    _ => true,
+                })
+            });
+            if !fixable {
+                log::debug!(
+                    "No fix found in diagnostic data for {:?}: {}",
+                    diagnostic.message,
+                    Value::Object(data.clone())
+                );
+            }
+            fixable
+        }
+        Some(data) => {
+            log::debug!(
+                "Couldn't interpret diagnostic data for {:?}: {data}",
+                diagnostic.message
+            );
+            false
+        }
+    }
+}
+
+/// Diagnostics with the same code and message are considered duplicates by
+/// `--aggregate-duplicates`.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct DuplicateKey<'a> {
+    code: Option<String>,
+    message: &'a str,
+}
+
+impl<'a> DuplicateKey<'a> {
+    fn new(diagnostic: &'a Diagnostic) -> Self {
+        Self {
+            code: diagnostic.code.as_ref().map(code_to_string),
+            message: &diagnostic.message,
+        }
+    }
+}
+
+/// Find diagnostics repeated across multiple files.
+///
+/// Returns `(file_index, diagnostic_index)` pairs in rendering order for each group of
+/// duplicates. Groups confined to a single file are omitted.
+fn index_duplicates<'a>(
+    files: &'a [FileDiagnostics],
+) -> HashMap<DuplicateKey<'a>, Vec<(usize, usize)>> {
+    let mut index = HashMap::<_, Vec<_>>::new();
+    for (file_index, file) in files.iter().enumerate() {
+        for (diagnostic_index, diagnostic) in file.diagnostics.iter().enumerate() {
+            index
+                .entry(DuplicateKey::new(diagnostic))
+                .or_default()
+                .push((file_index, diagnostic_index));
+        }
+    }
+    index.retain(|_, locations| {
+        locations
+            .iter()
+            .any(|(file_index, _)| *file_index != locations[0].0)
+    });
+    index
+}
+
+/// The other locations of a diagnostic aggregated by `--aggregate-duplicates`.
+struct DuplicateLocations<'a> {
+    files: &'a [FileDiagnostics],
+    locations: &'a [(usize, usize)],
+}
+
+impl<'a> Display for DuplicateLocations<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "    Also found in {} other {}:",
+            self.locations.len(),
+            if self.locations.len() == 1 {
+                "location"
+            } else {
+                "locations"
+            },
+        )?;
+        for &(file_index, diagnostic_index) in self.locations {
+            let file = &self.files[file_index];
+            write!(f, "    • {}:", file.path.display())?;
+            write_range(f, file.diagnostics[diagnostic_index].range)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Options for rendering diagnostics in the human-readable format.
+#[derive(Debug, Clone)]
+struct RenderOpts {
+    carets: bool,
+    /// The width to wrap messages to, or `None` to not wrap them.
+    wrap: Option<usize>,
+    icons: Icons,
+    show_source: bool,
+    show_rule_docs: bool,
+    annotate_fixmes: bool,
+    link_base: Option<Url>,
+    /// Whether to render terminal hyperlinks.
+    hyperlinks: bool,
+    sources: SourceCache,
+}
+
+impl RenderOpts {
+    fn new(opts: &Opts) -> Self {
+        Self {
+            carets: opts.carets,
+            wrap: if opts.no_wrap {
+                None
+            } else {
+                Some(opts.wrap.unwrap_or_else(textwrap::termwidth))
+            },
+            icons: opts.icons,
+            show_source: opts.show_source,
+            show_rule_docs: opts.show_rule_docs,
+            annotate_fixmes: opts.annotate_fixmes,
+            link_base: opts.link_base.clone(),
+            hyperlinks: opts.format == Format::Human
+                && opts.output.is_none()
+                && supports_hyperlinks::on(supports_hyperlinks::Stream::Stdout),
+            sources: SourceCache::default(),
+        }
+    }
+}
+
+struct PathDiagnostic<'a> {
+    path: &'a Path,
+    cwd: &'a Path,
+    diagnostic: &'a Diagnostic,
+    render: &'a RenderOpts,
+}
+
+impl<'a> PathDiagnostic<'a> {
+    fn write_location(&self, f: &mut Formatter<'_>, location: &Location) -> std::fmt::Result {
+        match to_relative_path(&location.uri, self.cwd) {
+            // Related information often points at library definitions outside the project,
+            // which are clearer as absolute paths than as a long chain of `../`.
+            Ok(path) if path.starts_with("..") => match location.uri.to_file_path() {
+                Ok(path) => write!(f, "{}:", path.display())?,
+                Err(()) => write!(f, "{}:", location.uri)?,
+            },
+            Ok(path) => {
+                write!(f, "{}:", path.display())?;
+            }
+            Err(_) => {
+                write!(f, "{}:", location.uri)?;
+            }
+        }
+        write_range(f, location.range)
+    }
+}
+
+impl<'a> Display for PathDiagnostic<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let link = self
+            .render
+            .link_base
+            .as_ref()
+            .filter(|_| self.render.hyperlinks)
+            .map(|base| link_url(base, self.path, self.diagnostic.range.start.line));
+        if let Some(link) = &link {
+            write!(f, "\x1b]8;;{link}\x1b\\")?;
+        }
+        write!(f, "{}:", self.path.display())?;
+        write_range(f, self.diagnostic.range)?;
+        if link.is_some() {
+            write!(f, "\x1b]8;;\x1b\\")?;
+        }
+        if let Some(code) = &self.diagnostic.code {
+            write!(f, " [")?;
+            match code {
+                lsp_types::NumberOrString::Number(code) => {
+                    write!(f, "{}", code.if_supports_color(Stdout, |text| text.bold()))?;
+                }
+                lsp_types::NumberOrString::String(code) => {
+                    write!(f, "{}", code.if_supports_color(Stdout, |text| text.bold()))?;
+                }
+            }
+            writeln!(f, "]")?;
+        } else {
+            writeln!(f)?;
+        }
+
+        let range = self.diagnostic.range;
+        // Multi-line ranges don't have a meaningful width.
+        if self.render.carets && range.start.line == range.end.line {
+            let line = self
+                .render
+                .sources
+                .lines(&self.cwd.join(self.path))
+                .and_then(|lines| lines.get(range.start.line as usize).cloned());
+            let (offset, width) = match &line {
+                Some(line) => {
+                    let before = utf16_prefix(line, range.start.character);
+                    let span = utf16_prefix(
+                        &line[before.len()..],
+                        range.end.character.saturating_sub(range.start.character),
+                    );
+                    writeln!(f, "{INDENT}{}", expand_tabs(line))?;
+                    (
+                        textwrap::core::display_width(&expand_tabs(before)),
+                        textwrap::core::display_width(&expand_tabs(span)),
+                    )
+                }
+                // Without the source, assume each character is one column wide.
+                None => (
+                    range.start.character as usize,
+                    range.end.character.saturating_sub(range.start.character) as usize,
+                ),
+            };
+            writeln!(
+                f,
+                "{INDENT}{}^{}",
+                " ".repeat(offset),
+                "~".repeat(width.saturating_sub(1))
+            )?;
+        }
+
+        let mut message = String::new();
+        if let Some(severity) = self.diagnostic.severity {
+            if let Some(icon) = write_severity_icon(severity, self.render.icons) {
+                message.push_str(&icon);
+                message.push(' ');
+            }
+            message.push_str(&write_severity(severity));
+        }
+        if self.render.show_source {
+            if let Some(source) = &self.diagnostic.source {
+                if !message.is_empty() {
+                    message.push(' ');
+                }
+                message.push_str(&format!("({source})"));
+            }
+        }
+        message.push_str(": ");
+        message.push_str(&self.diagnostic.message);
+        if self.render.annotate_fixmes {
+            if let Some(tag) = self
+                .render
+                .sources
+                .lines(&self.cwd.join(self.path))
+                .and_then(|lines| source::fixme(&lines, range.start.line as usize))
+            {
+                message.push_str(&format!(" (has {tag})"));
+            }
+        }
+        match self.render.wrap {
+            Some(width) => writeln!(f, "{}", textwrap::fill(&message, textwrap_opts(width)))?,
+            None => {
+                for line in message.lines() {
+                    writeln!(f, "{INDENT}{line}")?;
+                }
+            }
+        }
+
+        if let Some(code) = self
+            .diagnostic
+            .code
+            .as_ref()
+            .filter(|_| self.render.show_rule_docs)
+        {
+            let url = docs::rule_docs_url(&code_to_string(code));
+            if self.render.hyperlinks {
+                writeln!(f, "{INDENT}docs: \x1b]8;;{url}\x1b\\{url}\x1b]8;;\x1b\\")?;
+            } else {
+                writeln!(f, "{INDENT}docs: {url}")?;
+            }
+        }
+
+        if let Some(related_information) = &self.diagnostic.related_information {
+            for information in related_information {
+                if information.location.range == self.diagnostic.range
+                    && (information.message.is_empty()
+                        || information.message == self.diagnostic.message)
+                {
+                    // Ignore redundant related information.
+                    continue;
+                }
+                write!(f, "    • ")?;
+                self.write_location(f, &information.location)?;
+                if !information.message.is_empty() {
+                    writeln!(f, ": {}", information.message)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The distinct diagnostic codes found, for `--list-codes`.
+struct CodeList<'a> {
+    /// Codes, occurrence counts, and a sample message for each, most frequent first.
+    codes: Vec<(Option<String>, usize, &'a str)>,
+}
+
+impl<'a> CodeList<'a> {
+    fn new(files: &'a [FileDiagnostics]) -> Self {
+        let mut counts = HashMap::<Option<String>, (usize, &'a str)>::new();
+        for diagnostic in files.iter().flat_map(|file| &file.diagnostics) {
+            let (count, _) = counts
+                .entry(diagnostic.code.as_ref().map(code_to_string))
+                .or_insert((0, &diagnostic.message));
+            *count += 1;
+        }
+        let mut codes = counts
+            .into_iter()
+            .map(|(code, (count, message))| (code, count, message))
+            .collect::<Vec<_>>();
+        codes.sort_by(|(code_a, count_a, _), (code_b, count_b, _)| {
+            count_b.cmp(count_a).then_with(|| code_a.cmp(code_b))
+        });
+        Self { codes }
+    }
+}
+
+impl<'a> Display for CodeList<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .codes
+            .iter()
+            .map(|(code, _, _)| code.as_deref().unwrap_or(NO_CODE).len())
+            .max()
+            .unwrap_or(0);
+        for (code, count, message) in &self.codes {
+            // Only show the first line of multi-line messages.
+            let message = message.lines().next().unwrap_or_default();
+            writeln!(
+                f,
+                "{count:>6}  {:width$}  {message}",
+                code.as_deref()
+                    .unwrap_or(NO_CODE)
+                    .if_supports_color(Stdout, |text| text.bold()),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Placeholder for diagnostics without a code.
+const NO_CODE: &str = "(no code)";
+
+/// A summary line for diagnostics hidden by `--collapse`.
+struct CollapsedDiagnostics<'a> {
+    code: &'a str,
+    /// One-indexed line numbers of the hidden diagnostics.
+    lines: &'a [u32],
+}
+
+impl<'a> CollapsedDiagnostics<'a> {
+    /// The maximum number of line numbers to list.
+    const MAX_LINES: usize = 5;
+}
+
+impl<'a> Display for CollapsedDiagnostics<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "… {} more {} in this file ({} ",
+            self.lines.len(),
+            self.code.if_supports_color(Stdout, |text| text.bold()),
+            if self.lines.len() == 1 {
+                "line"
+            } else {
+                "lines"
+            },
+        )?;
+        for (i, line) in self.lines.iter().take(Self::MAX_LINES).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{line}")?;
+        }
+        if self.lines.len() > Self::MAX_LINES {
+            write!(f, ", …")?;
+        }
+        writeln!(f, ")")
+    }
+}
+
+fn write_range(f: &mut Formatter<'_>, range: Range) -> std::fmt::Result {
+    if range.start == range.end {
+        write_position(f, range.start)
+    } else {
+        write_position(f, range.start)?;
+        write!(f, "-")?;
+        write_position(f, range.end)?;
+        Ok(())
+    }
+}
+
+/// Clamp ranges ending at the start of the line after they start to the end of their first
+/// line, for `--normalize-ranges`.
+fn normalize_ranges(path: &Path, diagnostics: &mut [Diagnostic]) {
+    let spills_over =
+        |range: &Range| range.end.line == range.start.line + 1 && range.end.character == 0;
+    if !diagnostics
+        .iter()
+        .any(|diagnostic| spills_over(&diagnostic.range))
+    {
+        return;
+    }
+
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            log::debug!("Not normalizing ranges in {path:?}: {err}");
+            return;
+        }
+    };
+    let contents = String::from_utf8_lossy(&contents);
+    let lines = contents.lines().collect::<Vec<_>>();
+
+    for diagnostic in diagnostics {
+        let range = &mut diagnostic.range;
+        if !spills_over(range) {
+            continue;
+        }
+        let Some(line) = lines.get(range.start.line as usize) else {
+            continue;
+        };
+        // LSP positions count UTF-16 code units by default.
+        let length = line.encode_utf16().count() as u32;
+        range.end = Position {
+            line: range.start.line,
+            character: length.max(range.start.character),
+        };
+    }
+}
+
+/// The start of `line` up to `character` UTF-16 code units, which LSP positions count by
+/// default.
+fn utf16_prefix(line: &str, character: u32) -> &str {
+    let mut units = 0;
+    let end = line
+        .char_indices()
+        .find(|(_, c)| {
+            units += c.len_utf16();
+            units > character as usize
+        })
+        .map_or(line.len(), |(index, _)| index);
+    &line[..end]
+}
+
+/// Replace tabs with [`INDENT`], so they're as wide wherever they're displayed.
+fn expand_tabs(text: &str) -> Cow<'_, str> {
+    if text.contains('\t') {
+        Cow::Owned(text.replace('\t', INDENT))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn write_position(f: &mut Formatter<'_>, position: Position) -> std::fmt::Result {
+    // Lines and characters are zero-indexed.
+    write!(f, "{}:{}", position.line + 1, position.character + 1)
+}
+
+/// The first `lua-language-server` release with `--check_out_path`.
+const CHECK_OUT_PATH_VERSION: Version = Version {
+    major: 3,
+    minor: 10,
+    patch: 0,
+};
+
+/// How many of the last lines of `lua-language-server --check` output to search for an
+/// existing diagnostics file, if none of them is a summary line.
+const DIAGNOSTICS_PATH_LINES: usize = 5;
+
+/// Find the diagnostics file path in `lua-language-server --check` output.
+///
+/// The path is at the end of the summary line, like `Diagnosis complete, 10 problems found, see
+/// /path/to/check.json`, in whatever language `--locale` chose. Paths may contain spaces,
+/// may be quoted, and may be followed by a period. If no line looks like a summary, this
+/// falls back to the last few lines' trailing text which `exists`.
+///
+/// Returns `None` if the server says it found no problems, since some versions don't write
+/// a file then.
+fn find_diagnostics_path(stdout: &str, exists: impl Fn(&str) -> bool) -> Option<&str> {
+    let lines = output_lines(stdout);
+
+    // The summary in each locale `lua-language-server` ships.
+    static SUMMARY: OnceLock<Regex> = OnceLock::new();
+    let summary = SUMMARY.get_or_init(|| {
+        Regex::new(
+            r"(?:Diagnosis complete|Diagnóstico completo|诊断完成|診斷完成).*?(?:\bsee|\bveja|请查看|請查看)\s*(.+)$",
+        )
+        .expect("Diagnostics summary regex is valid")
+    });
+    let no_problems = no_problems_regex();
+    let found = lines.iter().rev().find_map(|line| {
+        if no_problems.is_match(line) {
+            return Some(None);
+        }
+        let captures = summary.captures(line)?;
+        Some(Some(trim_path(captures.get(1)?.as_str())))
+    });
+    if let Some(found) = found {
+        return found;
+    }
+
+    // Try every suffix starting after whitespace, longest first, so paths with spaces are
+    // found whole.
+    lines
+        .iter()
+        .rev()
+        .take(DIAGNOSTICS_PATH_LINES)
+        .find_map(|line| {
+            std::iter::once(0)
+                .chain(
+                    line.char_indices()
+                        .filter(|(_, c)| c.is_whitespace())
+                        .map(|(index, c)| index + c.len_utf8()),
+                )
+                .map(|start| trim_path(&line[start..]))
+                .find(|candidate| !candidate.is_empty() && exists(candidate))
+        })
+}
+
+/// Find the newest file matching `glob` in `logpath` modified since `since`, for when the
+/// server's output doesn't name its diagnostics file.
+fn find_results_file(logpath: &Path, glob: &Glob, since: SystemTime) -> Option<PathBuf> {
+    let mut newest = None;
+    let mut dirs = vec![logpath.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let Ok(modified) = metadata.modified() else {
+                continue;
+            };
+            let matches = path
+                .strip_prefix(logpath)
+                .is_ok_and(|relative| glob.is_match(relative));
+            if matches
+                && modified >= since
+                && newest
+                    .as_ref()
+                    .is_none_or(|(newest, _): &(SystemTime, PathBuf)| modified > *newest)
+            {
+                newest = Some((modified, path));
+            }
+        }
+    }
+    newest.map(|(_, path)| path)
+}
+
+/// Whether the last summary in `lua-language-server --check` output says it found no
+/// problems.
+fn reports_no_problems(stdout: &str) -> bool {
+    let no_problems = no_problems_regex();
+    output_lines(stdout)
+        .last()
+        .is_some_and(|line| no_problems.is_match(line))
+}
+
+/// Matches the server's "no problems found" summary, in each locale it ships.
+fn no_problems_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"(?i)no problems found|nenhum problema encontrado|没有发现问题|沒有發現問題")
+            .expect("No problems regex is valid")
+    })
+}
+
+/// The non-blank lines of the server's output.
+fn output_lines(stdout: &str) -> Vec<&str> {
+    // Progress is drawn with carriage returns, so each segment is a line of its own.
+    stdout
+        .split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Strip quotes and trailing punctuation from a path in the server's output.
+fn trim_path(path: &str) -> &str {
+    path.trim()
+        .trim_end_matches(['.', '。', ',', '，'])
+        .trim_matches(['"', '\'', '`', '“', '”', '「', '」'])
+}
+
+/// Link to a line in a hosted repository, like `<base>/<path>#L<line>`.
+///
+/// `line` is zero-indexed.
+fn link_url(base: &Url, path: &Path, line: u32) -> Url {
+    let mut url = base.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty();
+        segments.extend(path.components().filter_map(|component| match component {
+            std::path::Component::Normal(component) => Some(component.to_string_lossy()),
+            _ => None,
+        }));
+    }
+    url.set_fragment(Some(&format!("L{}", line + 1)));
+    url
+}
+
+/// A shell command line reproducing a [`Command`], for `--print-command`.
+struct CommandLine<'a>(&'a Command);
+
+impl<'a> Display for CommandLine<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cmd = self.0;
+        let current_dir = match cmd.get_current_dir() {
+            Some(dir) => Some(dir.to_path_buf()),
+            None => std::env::current_dir().ok(),
+        };
+        if let Some(dir) = current_dir {
+            write!(f, "cd {} && ", shell_quote(dir.as_os_str()))?;
+        }
+        if cmd.get_envs().len() > 0 {
+            write!(f, "env ")?;
+        }
+        // `env` needs its options before any assignments.
+        for (key, value) in cmd.get_envs() {
+            if value.is_none() {
+                write!(f, "-u {} ", shell_quote(key))?;
+            }
+        }
+        for (key, value) in cmd.get_envs() {
+            if let Some(value) = value {
+                write!(f, "{}={} ", shell_quote(key), shell_quote(value))?;
+            }
+        }
+        let program = Path::new(cmd.get_program());
+        let program = discover::find_executable(program).unwrap_or_else(|| program.to_path_buf());
+        write!(f, "{}", shell_quote(program.as_os_str()))?;
+        for arg in cmd.get_args() {
+            write!(f, " {}", shell_quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote a word for a POSIX shell, if needed.
+fn shell_quote(word: &OsStr) -> Cow<'_, str> {
+    let word = word.to_string_lossy();
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word
+    } else {
+        Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+    }
+}
+
+/// Check if `path` is under `base`, treating drive letters case-insensitively and `/` like
+/// `\` on Windows, like Windows itself does.
+fn path_starts_with(path: &Path, base: &Path) -> bool {
+    if cfg!(windows) {
+        normalize_windows_path(path).starts_with(normalize_windows_path(base))
+    } else {
+        path.starts_with(base)
+    }
+}
+
+/// Strip `base` from `path` like [`Path::strip_prefix`], but matching like
+/// [`path_starts_with`].
+fn strip_path_prefix(path: &Path, base: &Path) -> Option<PathBuf> {
+    // By components, since they may be spelled differently, like `c:\` and `C:\`.
+    path_starts_with(path, base)
+        .then(|| path.components().skip(base.components().count()).collect())
+}
+
+/// Uppercase a leading drive letter and use `\` as the separator.
+fn normalize_windows_path(path: &Path) -> PathBuf {
+    let mut path = path.to_string_lossy().replace('/', "\\");
+    if path.as_bytes().get(1) == Some(&b':') {
+        path[..1].make_ascii_uppercase();
+    }
+    PathBuf::from(path)
+}
+
+/// The user's cache directory, like `~/.cache`.
+fn cache_dir() -> Option<PathBuf> {
+    let non_empty = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+    if cfg!(windows) {
+        non_empty("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        non_empty("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+    } else {
+        non_empty("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    }
+}
+
+/// A cache directory for server files for a project, named after the project and a hash of
+/// its path so that projects with the same name don't collide.
+fn project_cache_dir(project: &Path) -> Option<PathBuf> {
+    // FNV-1a, which is stable across Rust versions, unlike `DefaultHasher`.
+    let hash = project
+        .as_os_str()
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    let name = project
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_owned());
+    Some(
+        cache_dir()?
+            .join("lualscheck")
+            .join(format!("{name}-{hash:016x}")),
+    )
+}
+
+/// Resolve and create a directory for the server to write to, defaulting to `name` in the
+/// project's cache directory.
+fn server_dir(
+    current_dir: &Path,
+    dir: Option<&Path>,
+    project_cache: Option<&Path>,
+    name: &str,
+    option: &str,
+) -> miette::Result<Option<PathBuf>> {
+    let dir = match dir {
+        Some(dir) => dir
+            .absolutize_from(current_dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to make path absolute: {dir:?}"))?
+            .into_owned(),
+        None => match project_cache {
+            Some(project_cache) => project_cache.join(name),
+            None => {
+                log::debug!(
+                    "Couldn't find a cache directory; using lua-language-server's default {name} directory"
+                );
+                return Ok(None);
+            }
+        },
+    };
+    match std::fs::create_dir_all(&dir) {
+        Ok(()) => Ok(Some(dir)),
+        Err(err) => Err(miette!(
+            help = format!("Pass `{option}` to use a writable directory"),
+            "Failed to create directory {}: {err}",
+            dir.display()
+        )),
+    }
+}
+
+fn to_relative_path(url: &Url, base: &Path) -> miette::Result<PathBuf> {
+    let scheme = url.scheme();
+    if scheme != "file" {
+        return Err(miette!(
+            "URL has unknown scheme {scheme:?}; expected \"file\""
+        ));
+    }
+    let path = url
+        .to_file_path()
+        .map_err(|()| miette!("Failed to convert URL to file path: {url:?}"))?;
+
+    // Paths under the base never climb out of it with `..`, even if they're spelled
+    // differently, like `c:\` and `C:\` on Windows.
+    if let Some(relative) = strip_path_prefix(&path, base) {
+        return Ok(relative);
+    }
+    Ok(pathdiff::diff_paths(&path, base).unwrap_or(path))
+}
+
+fn code_to_string(code: &lsp_types::NumberOrString) -> String {
+    match code {
+        lsp_types::NumberOrString::Number(code) => code.to_string(),
+        lsp_types::NumberOrString::String(code) => code.clone(),
+    }
+}
+
+fn write_severity(severity: DiagnosticSeverity) -> String {
+    if severity == DiagnosticSeverity::ERROR {
+        "error"
+            .if_supports_color(Stdout, |text| text.bright_red())
+            .to_string()
+    } else if severity == DiagnosticSeverity::WARNING {
+        "warning"
+            .if_supports_color(Stdout, |text| text.bright_yellow())
+            .to_string()
+    } else if severity == DiagnosticSeverity::INFORMATION {
+        "info"
+            .if_supports_color(Stdout, |text| text.bright_white())
+            .to_string()
+    } else if severity == DiagnosticSeverity::HINT {
+        "hint"
+            .if_supports_color(Stdout, |text| text.bright_cyan())
+            .to_string()
+    } else {
+        // Unknown severity
+        String::new()
+    }
+}
+
+fn write_severity_icon(severity: DiagnosticSeverity, icons: Icons) -> Option<String> {
+    let (unicode, ascii, color): (_, _, fn(&str) -> String) =
+        if severity == DiagnosticSeverity::ERROR {
+            ("✖", "E:", |icon| icon.bright_red().to_string())
+        } else if severity == DiagnosticSeverity::WARNING {
+            ("⚠", "W:", |icon| icon.bright_yellow().to_string())
+        } else if severity == DiagnosticSeverity::INFORMATION {
+            ("ℹ", "I:", |icon| icon.bright_white().to_string())
+        } else if severity == DiagnosticSeverity::HINT {
+            ("💡", "H:", |icon| icon.bright_cyan().to_string())
+        } else {
+            // Unknown severity
+            return None;
+        };
+    let icon = match icons {
+        Icons::Unicode => unicode,
+        Icons::Ascii => ascii,
+        Icons::None => return None,
+    };
+    Some(
+        icon.if_supports_color(Stdout, |icon| color(icon))
+            .to_string(),
+    )
+}
+
+/// The indent for diagnostic messages.
+const INDENT: &str = "    ";
+
+fn textwrap_opts(width: usize) -> textwrap::Options<'static> {
+    textwrap::Options::new(width.saturating_sub(INDENT.len()).max(1))
+        .initial_indent(INDENT)
+        .subsequent_indent(INDENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn test_exit_code_mapping() {
+        let mapping = "warning=7".parse::<ExitCodeMapping>().unwrap();
+        assert_eq!(mapping.severity, Severity::Warning);
+        assert_eq!(mapping.code, 7);
+        assert!("hint=0".parse::<ExitCodeMapping>().is_ok());
+        assert!("warning".parse::<ExitCodeMapping>().is_err());
+        assert!("warning=256".parse::<ExitCodeMapping>().is_err());
+    }
+
+    #[test]
+    fn test_exit_code_mapping_rejects_reserved_codes() {
+        assert!("error=2".parse::<ExitCodeMapping>().is_err());
+        assert!("error=3".parse::<ExitCodeMapping>().is_err());
+    }
+
+    #[test]
+    fn test_severity_exit_codes_avoid_reserved_codes() {
+        for severity in Severity::value_variants() {
+            let code = severity.exit_code(&[]);
+            assert_ne!(code, ExitCode::from(USAGE_ERROR));
+            assert_ne!(code, ExitCode::from(TOOL_FAILURE));
+        }
+        let mapping = "error=9".parse::<ExitCodeMapping>().unwrap();
+        assert_eq!(Severity::Error.exit_code(&[mapping]), ExitCode::from(9));
+    }
+
+    /// Hand-written `--check` output with carriage-return line endings, progress bar redraws and
+    /// a Windows path with a space in it.
+    const CRLF_CHECK_OUTPUT: &str = "Initializing ...\r\n\
+        >>>>>>>>>>>>>>>>>>>>========== 1/5\r\
+        >>>>>>>>>>>>>>>>>>>>>>>>>>>>>> 5/5\r\n\
+        Diagnosis complete, 5 problems found, see \
+        C:\\Users\\Jane Doe\\AppData\\Local\\Temp\\lua-language-server\\log\\check.json\r\n";
+
+    /// Hand-written `--check` output with newline line endings.
+    const LF_CHECK_OUTPUT: &str = "Initializing ...\n\
+        >>>>>>>>>>>>>>>>>>>>>>>>>>>>>> 5/5\n\
+        Diagnosis complete, 5 problems found, see \
+        /Users/jane/.cache/lua-language-server/log/check.json\n";
+
+    /// Hand-written `--check --locale zh-cn` output, not captured from a real run.
+    const HANDWRITTEN_ZH_CN_OUTPUT: &str =
+        include_str!("../tests/fixtures/stdout/handwritten-zh-cn-problems.txt");
+
+    const HANDWRITTEN_ZH_CN_NO_PROBLEMS_OUTPUT: &str =
+        include_str!("../tests/fixtures/stdout/handwritten-zh-cn-no-problems.txt");
+
+    #[test]
+    fn test_find_diagnostics_path_crlf() {
+        assert_eq!(
+            find_diagnostics_path(CRLF_CHECK_OUTPUT, |_| false),
+            Some(r"C:\Users\Jane Doe\AppData\Local\Temp\lua-language-server\log\check.json")
+        );
+    }
+
+    #[test]
+    fn test_find_diagnostics_path_lf() {
+        assert_eq!(
+            find_diagnostics_path(LF_CHECK_OUTPUT, |_| false),
+            Some("/Users/jane/.cache/lua-language-server/log/check.json")
+        );
+    }
+
+    #[test]
+    fn test_find_diagnostics_path_handwritten_zh_cn() {
+        assert_eq!(
+            find_diagnostics_path(HANDWRITTEN_ZH_CN_OUTPUT, |_| false),
+            Some("/home/jane/.cache/lua-language-server/log/check.json")
+        );
+    }
+
+    #[test]
+    fn test_locate_diagnostics_check_out_path() {
+        let dir = TempDir::new(&std::env::temp_dir()).unwrap();
+        let out_path = dir.path().join("check.json");
+        // Not written, because the check didn't finish.
+        assert_eq!(
+            locate_diagnostics(Some(&out_path), LF_CHECK_OUTPUT, None),
+            None
+        );
+        std::fs::write(&out_path, "[]").unwrap();
+        // The path in stdout is ignored.
+        assert_eq!(
+            locate_diagnostics(Some(&out_path), LF_CHECK_OUTPUT, None),
+            Some(out_path)
+        );
+    }
+
+    #[test]
+    fn test_locate_diagnostics_last_line_fallback() {
+        let dir = TempDir::new(&std::env::temp_dir()).unwrap();
+        let path = dir.path().join("check results.json");
+        std::fs::write(&path, "{}").unwrap();
+        // A summary the server might write in a locale without a known translation.
+        let stdout = format!(
+            "Initializing ...\nDiagnose terminé, voir {}\n",
+            path.display()
+        );
+        assert_eq!(locate_diagnostics(None, &stdout, None), Some(path.clone()));
+        // Files which don't exist aren't mistaken for the diagnostics.
+        let missing = dir.path().join("missing.json");
+        let stdout = format!("Diagnose terminé, voir {}\n", missing.display());
+        assert_eq!(locate_diagnostics(None, &stdout, None), None);
+    }
+
+    #[test]
+    fn test_reports_no_problems() {
+        for summary in [
+            "Diagnosis complete, no problems found",
+            "Diagnóstico completo, nenhum problema encontrado",
+            "诊断完成，没有发现问题",
+            "診斷完成，沒有發現問題",
+        ] {
+            let stdout = format!("Initializing ...\n>>>>>>>>>> 5/5\r{summary}\n");
+            assert!(reports_no_problems(&stdout), "{summary}");
+            assert_eq!(find_diagnostics_path(&stdout, |_| true), None, "{summary}");
+        }
+    }
+
+    #[test]
+    fn test_reports_problems() {
+        assert!(!reports_no_problems(LF_CHECK_OUTPUT));
+        assert!(!reports_no_problems(HANDWRITTEN_ZH_CN_OUTPUT));
+        assert!(!reports_no_problems(""));
+    }
+
+    #[test]
+    fn test_handwritten_zh_cn_no_problems() {
+        assert!(reports_no_problems(HANDWRITTEN_ZH_CN_NO_PROBLEMS_OUTPUT));
+        assert_eq!(
+            find_diagnostics_path(HANDWRITTEN_ZH_CN_NO_PROBLEMS_OUTPUT, |_| true),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_to_relative_path() {
+        let base = Path::new("/home/jane/project");
+        let relative = |url: &str| to_relative_path(&Url::parse(url).unwrap(), base).unwrap();
+
+        let inside = relative("file:///home/jane/project/src/foo.lua");
+        assert_eq!(inside, Path::new("src/foo.lua"));
+        assert_eq!(relative("file:///home/jane/project"), Path::new(""));
+        assert_eq!(relative("file:///home/jane/project/"), Path::new(""));
+        for path in [
+            "file:///home/jane/project/init.lua",
+            "file:///home/jane/project/lua/a/b/c.lua",
+        ] {
+            let relative = relative(path);
+            assert!(
+                !relative.starts_with(".."),
+                "{path} is displayed as {relative:?}"
+            );
+        }
+
+        // Only with `--relative-to`, since files outside the project aren't reported.
+        let outside = relative("file:///home/jane/other/foo.lua");
+        assert_eq!(outside, Path::new("../other/foo.lua"));
+        assert!(to_relative_path(&Url::parse("untitled:foo.lua").unwrap(), base).is_err());
+    }
+
+    #[test]
+    fn test_normalize_windows_path() {
+        for (path, normalized) in [
+            (r"c:\Users\Jane", r"C:\Users\Jane"),
+            (r"C:\Users\Jane", r"C:\Users\Jane"),
+            ("c:/Users/Jane", r"C:\Users\Jane"),
+            (r"d:\Program Files/lua", r"D:\Program Files\lua"),
+            // Only the drive letter is case-insensitive.
+            (r"c:\users\jane", r"C:\users\jane"),
+            (r"\\server\share", r"\\server\share"),
+            ("relative/path", r"relative\path"),
+        ] {
+            assert_eq!(
+                normalize_windows_path(Path::new(path)),
+                Path::new(normalized)
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_path_starts_with_drive_letter_case() {
+        let path = Path::new(r"c:\Users\Jane\project\init.lua");
+        assert!(path_starts_with(path, Path::new(r"C:\Users\Jane\project")));
+        assert!(path_starts_with(path, Path::new("C:/Users/Jane/project")));
+        assert!(path_starts_with(path, Path::new(r"c:\Users\Jane\project")));
+        assert!(!path_starts_with(path, Path::new(r"D:\Users\Jane\project")));
+        assert!(!path_starts_with(path, Path::new(r"C:\Users\Jane\proj")));
+        assert_eq!(
+            strip_path_prefix(path, Path::new(r"C:\Users\Jane")),
+            Some(PathBuf::from(r"project\init.lua"))
+        );
+    }
+
+    #[test]
+    fn test_strip_path_prefix() {
+        let path = Path::new("/home/jane/project/init.lua");
+        assert_eq!(
+            strip_path_prefix(path, Path::new("/home/jane")),
+            Some(PathBuf::from("project/init.lua"))
+        );
+        assert_eq!(strip_path_prefix(path, Path::new("/home/jan")), None);
+    }
+
+    /// Parse options like `main` does.
+    pub(crate) fn parse_opts(args: &[&str]) -> Opts {
+        let matches = Opts::command()
+            .try_get_matches_from(std::iter::once("lualscheck").chain(args.iter().copied()))
+            .unwrap();
+        Opts::from_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_relative_server_with_child_cwd() {
+        let temp = TempDir::new(&std::env::temp_dir()).unwrap();
+        let invocation = temp.path().join("invocation");
+        let child = temp.path().join("child");
+        std::fs::create_dir_all(invocation.join("bin")).unwrap();
+        std::fs::create_dir_all(child.join("bin")).unwrap();
+        std::fs::write(invocation.join("bin/lua-language-server"), "").unwrap();
+        std::fs::write(child.join("bin/lua-language-server"), "").unwrap();
+        std::fs::write(child.join("bin/child-only"), "").unwrap();
+
+        // Relative to the invocation's directory, not the server's.
+        for child_cwd in [child.to_str().unwrap(), "../child"] {
+            let opts = parse_opts(&["-c", "bin/lua-language-server", "--child-cwd", child_cwd]);
+            assert_eq!(
+                resolve_server(&opts, &invocation, false).unwrap(),
+                invocation.join("bin/lua-language-server")
+            );
+            let opts = parse_opts(&["-c", "./bin/child-only", "--child-cwd", child_cwd]);
+            assert_eq!(
+                resolve_server(&opts, &invocation, false).unwrap(),
+                invocation.join("bin/child-only")
+            );
+        }
+        let opts = parse_opts(&["-c", "bin/lua-language-server"]);
+        assert_eq!(
+            resolve_server(&opts, &invocation, false).unwrap(),
+            invocation.join("bin/lua-language-server")
+        );
+    }
+
+    #[test]
+    fn test_by_position_ignores_input_order() {
+        let diagnostic = |line, character, severity, message: &str| Diagnostic {
+            range: Range::new(
+                Position::new(line, character),
+                Position::new(line, character + 1),
+            ),
+            severity: Some(severity),
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        let diagnostics = vec![
+            diagnostic(3, 0, DiagnosticSeverity::WARNING, "c"),
+            diagnostic(0, 4, DiagnosticSeverity::HINT, "b"),
+            diagnostic(0, 4, DiagnosticSeverity::ERROR, "a"),
+            diagnostic(0, 0, DiagnosticSeverity::WARNING, "z"),
+            diagnostic(10, 2, DiagnosticSeverity::INFORMATION, "y"),
+            diagnostic(3, 0, DiagnosticSeverity::WARNING, "b"),
+        ];
+
+        let opts = parse_opts(&[]);
+        let render = RenderOpts::new(&opts);
+        let human = |diagnostics: &[Diagnostic]| {
+            by_position(diagnostics)
+                .into_iter()
+                .map(|diagnostic| {
+                    PathDiagnostic {
+                        path: Path::new("init.lua"),
+                        cwd: Path::new("/project"),
+                        diagnostic,
+                        render: &render,
+                    }
+                    .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let expected = human(&diagnostics);
+        assert_eq!(
+            expected
+                .iter()
+                .map(|rendered| rendered.lines().next().unwrap())
+                .collect::<Vec<_>>(),
+            [
+                "init.lua:1:1-1:2",
+                "init.lua:1:5-1:6",
+                "init.lua:1:5-1:6",
+                "init.lua:4:1-4:2",
+                "init.lua:4:1-4:2",
+                "init.lua:11:3-11:4",
+            ]
+        );
+        // Rotate the input, then shuffle it deterministically.
+        for rotation in 0..diagnostics.len() {
+            let mut shuffled = diagnostics.clone();
+            shuffled.rotate_left(rotation);
+            for index in (1..shuffled.len()).rev() {
+                shuffled.swap(index, (index * 7 + rotation) % (index + 1));
+            }
+            assert_eq!(human(&shuffled), expected, "rotated by {rotation}");
+            shuffled.reverse();
+            assert_eq!(
+                human(&shuffled),
+                expected,
+                "rotated by {rotation} and reversed"
+            );
+        }
+    }
+
+    #[test]
+    fn test_carets() {
+        let dir = TempDir::new(&std::env::temp_dir()).unwrap();
+        std::fs::write(
+            dir.path().join("init.lua"),
+            "local 诊断, x = 1\n\tlocal unused = 1\n",
+        )
+        .unwrap();
+        let diagnostic = |line, start, end| Diagnostic {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            message: "Unused local.".to_owned(),
+            ..Default::default()
+        };
+
+        let opts = parse_opts(&["--carets"]);
+        let render = RenderOpts::new(&opts);
+        let rendered = |path: &str, diagnostic: &Diagnostic| {
+            PathDiagnostic {
+                path: Path::new(path),
+                cwd: dir.path(),
+                diagnostic,
+                render: &render,
+            }
+            .to_string()
+        };
+
+        // `诊断` is two UTF-16 code units, but four columns wide.
+        assert_eq!(
+            rendered("init.lua", &diagnostic(0, 10, 11)),
+            "init.lua:1:11-1:12\n    local 诊断, x = 1\n                ^\n    : Unused local.\n"
+        );
+        assert_eq!(
+            rendered("init.lua", &diagnostic(0, 6, 8)),
+            "init.lua:1:7-1:9\n    local 诊断, x = 1\n          ^~~~\n    : Unused local.\n"
+        );
+        // Tabs are expanded the same way in the line and the marker's offset.
+        assert_eq!(
+            rendered("init.lua", &diagnostic(1, 7, 13)),
+            "init.lua:2:8-2:14\n        local unused = 1\n              ^~~~~~\n    : Unused local.\n"
+        );
+        // Without the source, each character is assumed to be one column.
+        assert_eq!(
+            rendered("missing.lua", &diagnostic(0, 3, 5)),
+            "missing.lua:1:4-1:6\n       ^~\n    : Unused local.\n"
+        );
+    }
+
+    /// The server's `--check` output for a huge project: `len` bytes of progress bars redrawn
+    /// with carriage returns, then the summary.
+    fn huge_check_output(len: u64) -> impl Read {
+        /// Repeats a progress bar redraw forever.
+        struct Progress(usize);
+
+        impl Read for Progress {
+            fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+                const REDRAW: &[u8] = b"\r>>>>>>>>>>>>>>>>>>>>========== 51234/99999";
+                for byte in out.iter_mut() {
+                    *byte = REDRAW[self.0 % REDRAW.len()];
+                    self.0 += 1;
+                }
+                Ok(out.len())
+            }
+        }
+
+        Progress(0).take(len).chain(
+            "\nDiagnosis complete, 5 problems found, see /home/jane/.cache/lua-language-server/log/check.json\n"
+                .as_bytes(),
+        )
+    }
+
+    /// Several hundred MB.
+    const HUGE_OUTPUT_LEN: u64 = 300 * 1024 * 1024;
+
+    #[test]
+    fn test_read_huge_output() {
+        let mut forwarded = 0;
+        let tail = read_output(huge_check_output(HUGE_OUTPUT_LEN), |bytes| {
+            forwarded += bytes.len();
+            Ok(())
+        })
+        .unwrap();
+        let (contents, dropped) = tail.finish();
+        assert_eq!((contents.len() + dropped) as u64, forwarded as u64);
+        assert!(forwarded as u64 > HUGE_OUTPUT_LEN);
+        assert!(contents.len() <= STDOUT_CAP);
+        assert_eq!(
+            find_diagnostics_path(&String::from_utf8_lossy(&contents), |_| false),
+            Some("/home/jane/.cache/lua-language-server/log/check.json")
+        );
+    }
+
+    #[test]
+    fn test_output_tail_is_bounded() {
+        let mut output = huge_check_output(HUGE_OUTPUT_LEN);
+        let mut tail = OutputTail::default();
+        let mut buffer = vec![0; 1024];
+        loop {
+            let n = output.read(&mut buffer).unwrap();
+            if n == 0 {
+                break;
+            }
+            tail.push(&buffer[..n]);
+            assert!(tail.contents.len() <= 2 * STDOUT_CAP);
+        }
+        // Starting at a line break, rather than partway through a progress bar.
+        let (contents, _) = tail.finish();
+        assert!(contents.starts_with(b">>>>"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use miette::Context;
+use miette::IntoDiagnostic;
+
+use crate::trace;
+use crate::Opts;
+
+/// The level logged to `--log-file`.
+const LOG_FILE_LEVEL: LevelFilter = LevelFilter::Debug;
+
+/// Set the log level from `-v` and `-q`, unless `RUST_LOG` is set, and open `--log-file`.
+pub fn init(opts: &Opts) -> miette::Result<()> {
+    let levels = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+    let level = if opts.silent {
+        LevelFilter::Off
+    } else {
+        // Warnings are shown by default.
+        let index = (2 + opts.verbose as isize - opts.quiet as isize).clamp(0, 5);
+        levels[index as usize]
+    };
+
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder.filter_level(level);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let terminal = builder.build();
+
+    let file = match &opts.log_file {
+        Some(path) => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to open log file: {path:?}"))?;
+            writeln!(
+                file,
+                "==== lualscheck {} started at {} (pid {}): {:?}",
+                env!("CARGO_PKG_VERSION"),
+                humantime::format_rfc3339_seconds(SystemTime::now()),
+                std::process::id(),
+                std::env::args().collect::<Vec<_>>(),
+            )
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write to log file: {path:?}"))?;
+            Some(Mutex::new(file))
+        }
+        None => None,
+    };
+
+    let max_level = if file.is_some() || trace::enabled() {
+        terminal.filter().max(LOG_FILE_LEVEL)
+    } else {
+        terminal.filter()
+    };
+    log::set_boxed_logger(Box::new(TeeLogger { terminal, file }))
+        .into_diagnostic()
+        .wrap_err("Failed to initialize logging")?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Logs to the terminal and, optionally, a file and the `--trace` recorder.
+struct TeeLogger {
+    terminal: env_logger::Logger,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata)
+            || ((self.file.is_some() || trace::enabled()) && metadata.level() <= LOG_FILE_LEVEL)
+    }
+
+    fn log(&self, record: &Record) {
+        self.terminal.log(record);
+
+        if record.level() > LOG_FILE_LEVEL {
+            return;
+        }
+        trace::log(record);
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(
+                    file,
+                    "{} {:5} {}: {}",
+                    humantime::format_rfc3339_millis(SystemTime::now()),
+                    record.level(),
+                    record.target(),
+                    record.args()
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
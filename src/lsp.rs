@@ -0,0 +1,455 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ChildStdin;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::time::Instant;
+
+use lsp_types::Diagnostic;
+use lsp_types::PublishDiagnosticsParams;
+use lsp_types::Url;
+use miette::miette;
+use miette::Context;
+use miette::IntoDiagnostic;
+use serde_json::json;
+use serde_json::Value;
+
+/// How long the server must be quiet, once every opened file has diagnostics and no work
+/// is in progress, before its diagnostics are considered complete.
+const QUIET_TIME: Duration = Duration::from_millis(500);
+
+/// How long the server must be quiet, with no work in progress, before its diagnostics are
+/// considered complete even if some opened files don't have diagnostics yet.
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// How long to wait for the server to respond to `shutdown` and exit.
+pub const SHUTDOWN_TIME: Duration = Duration::from_secs(2);
+
+/// Why [`Client::collect`] stopped collecting diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The server finished diagnosing the opened files.
+    Settled,
+    /// The `stop` callback asked to stop.
+    Stopped,
+    /// The deadline passed.
+    TimedOut,
+    /// The server closed its stdout, usually by exiting.
+    Exited,
+}
+
+/// A message from the server, after the client has handled it.
+enum Event {
+    Response { id: i64, error: Option<Value> },
+    Published(Url),
+    Other,
+}
+
+/// A minimal Language Server Protocol client for `lua-language-server`, which collects the
+/// diagnostics it publishes for files as they're opened.
+///
+/// Messages are JSON-RPC objects framed with `Content-Length` headers over the server's
+/// stdin and stdout.
+pub struct Client {
+    stdin: ChildStdin,
+    messages: Receiver<miette::Result<Value>>,
+    next_id: i64,
+    /// Set once the server closes its stdout.
+    exited: bool,
+    /// `$/progress` tokens which have begun but not ended.
+    progress: HashSet<String>,
+    /// The diagnostics most recently published for each document, by URI.
+    pub diagnostics: BTreeMap<String, Vec<Diagnostic>>,
+}
+
+impl Client {
+    /// Start reading messages from the server's stdout on a background thread.
+    pub fn new(stdin: ChildStdin, stdout: impl Read + Send + 'static) -> Self {
+        let (sender, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            loop {
+                let message = match read_message(&mut reader) {
+                    Ok(Some(message)) => Ok(message),
+                    Ok(None) => break,
+                    Err(err) => Err(err),
+                };
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            stdin,
+            messages,
+            next_id: 1,
+            exited: false,
+            progress: HashSet::new(),
+            diagnostics: BTreeMap::new(),
+        }
+    }
+
+    /// Initialize the server for the `root` workspace, open `files`, and collect their
+    /// diagnostics until the server settles.
+    ///
+    /// `stop` is called with each set of published diagnostics, and collection stops early
+    /// if it returns `true`.
+    pub fn collect(
+        &mut self,
+        root: &Path,
+        files: &[PathBuf],
+        deadline: Option<Instant>,
+        mut stop: impl FnMut(&Url, &[Diagnostic]) -> bool,
+    ) -> miette::Result<Outcome> {
+        let root_uri = file_url(root)?;
+        let name = root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| root.display().to_string());
+        let id = self.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "clientInfo": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "rootUri": root_uri,
+                "workspaceFolders": [{ "uri": root_uri, "name": name }],
+                "capabilities": {
+                    "window": { "workDoneProgress": true },
+                    "workspace": { "configuration": true, "workspaceFolders": true },
+                    "textDocument": {
+                        "publishDiagnostics": { "relatedInformation": true },
+                    },
+                },
+            }),
+        )?;
+        if let Some(outcome) = self.wait_for_response(id, deadline)? {
+            return Ok(outcome);
+        }
+        self.notify("initialized", json!({}))?;
+
+        let mut pending = HashSet::new();
+        for file in files {
+            let text = match std::fs::read(file) {
+                Ok(text) => String::from_utf8_lossy(&text).into_owned(),
+                Err(err) => {
+                    log::warn!("Failed to read {}: {err}", file.display());
+                    continue;
+                }
+            };
+            self.notify(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": {
+                        "uri": file_url(file)?,
+                        "languageId": "lua",
+                        "version": 1,
+                        "text": text,
+                    },
+                }),
+            )?;
+            pending.insert(file.clone());
+        }
+        log::debug!("Opened {} files", pending.len());
+
+        let mut last_activity = Instant::now();
+        loop {
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Ok(Outcome::TimedOut);
+            }
+            let quiet = now - last_activity;
+            if self.progress.is_empty()
+                && ((pending.is_empty() && quiet >= QUIET_TIME) || quiet >= SETTLE_TIME)
+            {
+                if !pending.is_empty() {
+                    log::debug!(
+                        "{} opened files don't have diagnostics; assuming they have none",
+                        pending.len()
+                    );
+                }
+                return Ok(Outcome::Settled);
+            }
+
+            match self.next(QUIET_TIME)? {
+                Some(Event::Published(uri)) => {
+                    last_activity = Instant::now();
+                    if let Ok(path) = uri.to_file_path() {
+                        pending.remove(&path);
+                    }
+                    let diagnostics = self
+                        .diagnostics
+                        .get(uri.as_str())
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
+                    if stop(&uri, diagnostics) {
+                        return Ok(Outcome::Stopped);
+                    }
+                }
+                Some(_) => last_activity = Instant::now(),
+                None if self.exited => return Ok(Outcome::Exited),
+                None => {}
+            }
+        }
+    }
+
+    /// Ask the server to shut down and exit, waiting up to [`SHUTDOWN_TIME`] for it to
+    /// acknowledge.
+    pub fn shutdown(&mut self) -> miette::Result<()> {
+        let id = self.request("shutdown", Value::Null)?;
+        let deadline = Instant::now() + SHUTDOWN_TIME;
+        if self.wait_for_response(id, Some(deadline))?.is_none() {
+            self.notify("exit", Value::Null)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the response to a request, returning why it didn't arrive, if it didn't.
+    fn wait_for_response(
+        &mut self,
+        id: i64,
+        deadline: Option<Instant>,
+    ) -> miette::Result<Option<Outcome>> {
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => timeout,
+                    None => return Ok(Some(Outcome::TimedOut)),
+                },
+                None => SETTLE_TIME,
+            };
+            match self.next(timeout)? {
+                Some(Event::Response {
+                    id: response_id,
+                    error,
+                }) if response_id == id => {
+                    return match error {
+                        Some(error) => {
+                            Err(miette!("lua-language-server returned an error: {error}"))
+                        }
+                        None => Ok(None),
+                    };
+                }
+                None if self.exited => return Ok(Some(Outcome::Exited)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the next message from the server, and handle it.
+    fn next(&mut self, timeout: Duration) -> miette::Result<Option<Event>> {
+        let message = match self.messages.recv_timeout(timeout) {
+            Ok(message) => message?,
+            Err(RecvTimeoutError::Timeout) => return Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.exited = true;
+                return Ok(None);
+            }
+        };
+        let method = message.get("method").and_then(Value::as_str);
+        let id = message.get("id");
+        match (method, id) {
+            (Some(method), Some(id)) => {
+                // The server's requests are only for optional features, so give the default
+                // answer. `workspace/configuration` wants one value per item; `null` means
+                // the server should use its own configuration.
+                let result = match method {
+                    "workspace/configuration" => {
+                        let items = message
+                            .pointer("/params/items")
+                            .and_then(Value::as_array)
+                            .map_or(0, Vec::len);
+                        Value::Array(vec![Value::Null; items])
+                    }
+                    _ => Value::Null,
+                };
+                log::trace!("lua-language-server request: {method}");
+                self.send(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))?;
+                Ok(Some(Event::Other))
+            }
+            (Some(method), None) => {
+                let params = message.get("params").cloned().unwrap_or_default();
+                self.handle_notification(method, params)
+            }
+            (None, Some(id)) => Ok(Some(Event::Response {
+                id: id.as_i64().unwrap_or_default(),
+                error: message.get("error").cloned(),
+            })),
+            (None, None) => {
+                log::debug!("Ignoring unexpected message from lua-language-server: {message}");
+                Ok(Some(Event::Other))
+            }
+        }
+    }
+
+    fn handle_notification(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> miette::Result<Option<Event>> {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let params: PublishDiagnosticsParams = serde_json::from_value(params)
+                    .into_diagnostic()
+                    .wrap_err("Failed to deserialize published diagnostics")?;
+                log::trace!(
+                    "{} diagnostics published for {}",
+                    params.diagnostics.len(),
+                    params.uri
+                );
+                if params.diagnostics.is_empty() {
+                    self.diagnostics.remove(params.uri.as_str());
+                } else {
+                    self.diagnostics
+                        .insert(params.uri.to_string(), params.diagnostics);
+                }
+                Ok(Some(Event::Published(params.uri)))
+            }
+            "$/progress" => {
+                let token = params
+                    .get("token")
+                    .map(Value::to_string)
+                    .unwrap_or_default();
+                match params.pointer("/value/kind").and_then(Value::as_str) {
+                    Some("begin") => {
+                        log::debug!(
+                            "lua-language-server: {}",
+                            params
+                                .pointer("/value/title")
+                                .and_then(Value::as_str)
+                                .unwrap_or("working")
+                        );
+                        self.progress.insert(token);
+                    }
+                    Some("end") => {
+                        self.progress.remove(&token);
+                    }
+                    _ => {}
+                }
+                Ok(Some(Event::Other))
+            }
+            "window/logMessage" | "window/showMessage" => {
+                let text = params
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                // Errors are type 1, warnings are type 2.
+                match params.get("type").and_then(Value::as_u64) {
+                    Some(1) => log::warn!("lua-language-server: {text}"),
+                    _ => log::debug!("lua-language-server: {text}"),
+                }
+                Ok(Some(Event::Other))
+            }
+            _ => {
+                log::trace!("lua-language-server notification: {method}");
+                Ok(Some(Event::Other))
+            }
+        }
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> miette::Result<i64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+        Ok(id)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> miette::Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn send(&mut self, message: &Value) -> miette::Result<()> {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len())
+            .and_then(|()| self.stdin.flush())
+            .into_diagnostic()
+            .wrap_err("Failed to write to lua-language-server")
+    }
+}
+
+/// Read one message, or `None` at the end of the stream.
+fn read_message(reader: &mut impl BufRead) -> miette::Result<Option<Value>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).into_diagnostic()? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        miette!("lua-language-server sent a message without a `Content-Length` header")
+    })?;
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                io::Error::new(err.kind(), "lua-language-server sent a truncated message")
+            }
+            _ => err,
+        })
+        .into_diagnostic()?;
+    serde_json::from_slice(&body)
+        .into_diagnostic()
+        .wrap_err("Failed to deserialize message from lua-language-server")
+        .map(Some)
+}
+
+fn file_url(path: &Path) -> miette::Result<Url> {
+    Url::from_file_path(path).map_err(|()| miette!("Failed to convert path to a URL: {path:?}"))
+}
+
+/// Find the Lua files under `path` to open, skipping hidden directories like `.git` and
+/// paths for which `skip` returns `true`.
+pub fn lua_files(path: &Path, skip: &dyn Fn(&Path) -> bool) -> miette::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if path.is_file() {
+        files.push(path.to_owned());
+        return Ok(files);
+    }
+    let mut dirs = vec![path.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read directory: {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry.into_diagnostic()?;
+            let path = entry.path();
+            if skip(&path) {
+                continue;
+            }
+            let file_type = entry.file_type().into_diagnostic()?;
+            if file_type.is_dir() {
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    dirs.push(path);
+                }
+            } else if path.extension().is_some_and(|extension| extension == "lua") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,99 @@
+use crate::Severity;
+
+/// A named set of diagnostic codes to hide or reclassify, for `--preset`.
+///
+/// `--exclude-code`, `--exclude-code-from`, and `--severity` are applied on top of the
+/// preset, so a `--severity` for a code the preset hides shows it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Preset {
+    /// Fail on type mismatches, possible `nil` uses, missing and extra arguments and return
+    /// values, undefined names, and unused code.
+    ///
+    /// Makes `assign-type-mismatch`, `cast-local-type`, `inject-field`,
+    /// `missing-fields`, `missing-parameter`, `missing-return`, `missing-return-value`,
+    /// `need-check-nil`, `param-type-mismatch`, `redundant-parameter`,
+    /// `return-type-mismatch`, `undefined-field`, and `undefined-global` errors, and
+    /// `unused-function`, `unused-local`, and `unused-vararg` warnings.
+    Strict,
+    /// Fail on undefined globals and calls with the wrong number of arguments, and hide
+    /// style checks.
+    ///
+    /// Makes `missing-parameter`, `redundant-parameter`, and `undefined-global` errors, and
+    /// hides `codestyle-check`, `name-style-check`, `spell-check`, and `trailing-space`.
+    Recommended,
+    /// Only show likely bugs, hiding style checks and unused code.
+    ///
+    /// Hides `codestyle-check`, `empty-block`, `lowercase-global`, `name-style-check`,
+    /// `spell-check`, `trailing-space`, `unused-function`, `unused-label`, `unused-local`,
+    /// and `unused-vararg`.
+    Minimal,
+}
+
+/// The codes a preset hides and reclassifies.
+#[derive(Debug)]
+pub struct Rules {
+    pub exclude: &'static [&'static str],
+    pub severities: &'static [(&'static str, Severity)],
+}
+
+const STRICT: Rules = Rules {
+    exclude: &[],
+    severities: &[
+        ("assign-type-mismatch", Severity::Error),
+        ("cast-local-type", Severity::Error),
+        ("inject-field", Severity::Error),
+        ("missing-fields", Severity::Error),
+        ("missing-parameter", Severity::Error),
+        ("missing-return", Severity::Error),
+        ("missing-return-value", Severity::Error),
+        ("need-check-nil", Severity::Error),
+        ("param-type-mismatch", Severity::Error),
+        ("redundant-parameter", Severity::Error),
+        ("return-type-mismatch", Severity::Error),
+        ("undefined-field", Severity::Error),
+        ("undefined-global", Severity::Error),
+        ("unused-function", Severity::Warning),
+        ("unused-local", Severity::Warning),
+        ("unused-vararg", Severity::Warning),
+    ],
+};
+
+const RECOMMENDED: Rules = Rules {
+    exclude: &[
+        "codestyle-check",
+        "name-style-check",
+        "spell-check",
+        "trailing-space",
+    ],
+    severities: &[
+        ("missing-parameter", Severity::Error),
+        ("redundant-parameter", Severity::Error),
+        ("undefined-global", Severity::Error),
+    ],
+};
+
+const MINIMAL: Rules = Rules {
+    exclude: &[
+        "codestyle-check",
+        "empty-block",
+        "lowercase-global",
+        "name-style-check",
+        "spell-check",
+        "trailing-space",
+        "unused-function",
+        "unused-label",
+        "unused-local",
+        "unused-vararg",
+    ],
+    severities: &[],
+};
+
+impl Preset {
+    pub fn rules(self) -> &'static Rules {
+        match self {
+            Preset::Strict => &STRICT,
+            Preset::Recommended => &RECOMMENDED,
+            Preset::Minimal => &MINIMAL,
+        }
+    }
+}
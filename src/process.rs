@@ -0,0 +1,465 @@
+use std::io;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::OnceLock;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How many bytes of the server's stderr to keep.
+const STDERR_CAP: usize = 64 * 1024;
+
+/// How many lines of the server's stderr to show when it fails.
+pub const STDERR_TAIL_LINES: usize = 20;
+
+/// How often to check if the server has exited when waiting with a deadline.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The running server's process ID, or 0, for the interrupt handler.
+static CHILD: AtomicU32 = AtomicU32::new(0);
+
+/// The running `--docker` container's runtime and name, for the interrupt handler.
+static CONTAINER: OnceLock<(PathBuf, String)> = OnceLock::new();
+
+/// Whether stderr is a terminal that the interrupt handler should clear the line of.
+static STDERR_TERMINAL: AtomicBool = AtomicBool::new(false);
+
+/// Kill the server when `lualscheck` is interrupted with Ctrl-C or terminated, then exit
+/// with the conventional status (130 or 143).
+pub fn handle_interrupts() {
+    STDERR_TERMINAL.store(std::io::stderr().is_terminal(), Ordering::Relaxed);
+    #[cfg(unix)]
+    {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            // SAFETY: The handler only calls async-signal-safe functions.
+            unsafe { libc::signal(signal, handler) };
+        }
+    }
+    #[cfg(windows)]
+    {
+        // SAFETY: The handler runs on its own thread and doesn't need any other setup.
+        unsafe { windows::SetConsoleCtrlHandler(Some(windows::on_ctrl), 1) };
+    }
+}
+
+/// Record the running server for the interrupt handler.
+pub fn track(child: &Child) {
+    CHILD.store(child.id(), Ordering::SeqCst);
+}
+
+/// Record that the server is running in a container, so the interrupt handler stops the
+/// container rather than only the runtime's client.
+pub fn track_container(runtime: &Path, name: &str) {
+    let _ = CONTAINER.set((runtime.to_owned(), name.to_owned()));
+}
+
+/// Forget the server once it's exited, so the interrupt handler doesn't kill a reused ID.
+pub fn untrack() {
+    CHILD.store(0, Ordering::SeqCst);
+}
+
+fn interrupted_message(terminated: bool) -> &'static [u8] {
+    match (STDERR_TERMINAL.load(Ordering::Relaxed), terminated) {
+        // Clear any progress bar or spinner first.
+        (true, false) => b"\r\x1b[2KInterrupted\n",
+        (true, true) => b"\r\x1b[2KTerminated\n",
+        (false, false) => b"Interrupted\n",
+        (false, true) => b"Terminated\n",
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    let pid = CHILD.load(Ordering::SeqCst);
+    let message = interrupted_message(signal == libc::SIGTERM);
+    // Container runtimes forward `SIGTERM` to the container, but can't forward `SIGKILL`.
+    let kill_signal = if CONTAINER.get().is_some() {
+        libc::SIGTERM
+    } else {
+        libc::SIGKILL
+    };
+    // SAFETY: `kill`, `write`, and `_exit` are async-signal-safe.
+    unsafe {
+        if pid != 0 {
+            libc::kill(-(pid as libc::pid_t), kill_signal);
+        }
+        libc::write(2, message.as_ptr().cast(), message.len());
+        libc::_exit(128 + signal);
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::io::Write;
+    use std::sync::atomic::Ordering;
+    use std::thread::JoinHandle;
+
+    use std::ffi::c_void;
+
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    pub const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+    pub const ABOVE_NORMAL_PRIORITY_CLASS: u32 = 0x0000_8000;
+
+    pub const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS: i32 = 9;
+    pub const JOB_OBJECT_LIMIT_JOB_MEMORY: u32 = 0x0000_0200;
+
+    /// `JOBOBJECT_BASIC_LIMIT_INFORMATION`.
+    #[repr(C)]
+    pub struct JobObjectBasicLimitInformation {
+        pub per_process_user_time_limit: i64,
+        pub per_job_user_time_limit: i64,
+        pub limit_flags: u32,
+        pub minimum_working_set_size: usize,
+        pub maximum_working_set_size: usize,
+        pub active_process_limit: u32,
+        pub affinity: usize,
+        pub priority_class: u32,
+        pub scheduling_class: u32,
+    }
+
+    /// `JOBOBJECT_EXTENDED_LIMIT_INFORMATION`.
+    #[repr(C)]
+    pub struct JobObjectExtendedLimitInformation {
+        pub basic: JobObjectBasicLimitInformation,
+        pub io_info: [u64; 6],
+        pub process_memory_limit: usize,
+        pub job_memory_limit: usize,
+        pub peak_process_memory_used: usize,
+        pub peak_job_memory_used: usize,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn SetConsoleCtrlHandler(
+            handler: Option<unsafe extern "system" fn(u32) -> i32>,
+            add: i32,
+        ) -> i32;
+
+        pub fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> *mut c_void;
+
+        pub fn SetInformationJobObject(
+            job: *mut c_void,
+            class: i32,
+            info: *const c_void,
+            length: u32,
+        ) -> i32;
+
+        pub fn AssignProcessToJobObject(job: *mut c_void, process: *mut c_void) -> i32;
+    }
+
+    /// Console control handlers run on a new thread, so unlike Unix signal handlers they can
+    /// do anything.
+    pub unsafe extern "system" fn on_ctrl(event: u32) -> i32 {
+        let pid = super::CHILD.load(Ordering::SeqCst);
+        if let Some((runtime, name)) = super::CONTAINER.get().filter(|_| pid != 0) {
+            let _ = std::process::Command::new(runtime)
+                .arg("kill")
+                .arg(name)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status();
+        }
+        if pid != 0 {
+            let _ = std::process::Command::new("taskkill")
+                .args(["/T", "/F", "/PID"])
+                .arg(pid.to_string())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status();
+        }
+        let terminated = !matches!(event, CTRL_C_EVENT | CTRL_BREAK_EVENT);
+        let _ = std::io::stderr().write_all(super::interrupted_message(terminated));
+        std::process::exit(if terminated { 143 } else { 130 });
+    }
+}
+
+/// Read a stream to the end on a thread, keeping only the last [`STDERR_CAP`] bytes.
+pub fn read_tail(mut stream: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut contents = Vec::new();
+        let mut buffer = vec![0; 1024];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    contents.extend(&buffer[..n]);
+                    // Trim in batches, rather than on every read.
+                    if contents.len() > 2 * STDERR_CAP {
+                        contents.drain(..contents.len() - STDERR_CAP);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::debug!("Failed to read lua-language-server stderr: {err}");
+                    break;
+                }
+            }
+        }
+        if contents.len() > STDERR_CAP {
+            contents.drain(..contents.len() - STDERR_CAP);
+        }
+        contents
+    })
+}
+
+/// Wait for a [`read_tail`] thread, decoding its output lossily.
+pub fn join_tail(handle: Option<JoinHandle<Vec<u8>>>) -> String {
+    handle
+        .and_then(|handle| handle.join().ok())
+        .map(|contents| String::from_utf8_lossy(&contents).into_owned())
+        .unwrap_or_default()
+}
+
+/// The last `count` non-blank lines of `text`.
+pub fn tail_lines(text: &str, count: usize) -> String {
+    let lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
+
+/// A hint for when the server crashes.
+const CRASH_HINT: &str =
+    "lua-language-server crashed; its logs may show which file it was checking";
+
+/// Describe how the server exited unsuccessfully, naming the signal which killed it or the
+/// Windows status it crashed with, along with a hint for crashes.
+pub fn describe_exit(status: ExitStatus) -> (String, Option<&'static str>) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            let mut description = match signal_name(signal) {
+                Some(name) => format!("killed by {name} (signal {signal})"),
+                None => format!("killed by signal {signal}"),
+            };
+            if status.core_dumped() {
+                description.push_str(", core dumped");
+            }
+            let hint = match signal {
+                libc::SIGKILL => Some(
+                    "SIGKILL often means the system ran out of memory and killed lua-language-server; check `dmesg` or the system log",
+                ),
+                libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGABRT | libc::SIGFPE => {
+                    Some(CRASH_HINT)
+                }
+                _ => None,
+            };
+            return (description, hint);
+        }
+    }
+    #[cfg(windows)]
+    if let Some(code) = status.code() {
+        // Crashes exit with an `NTSTATUS` code.
+        let status_name = match code as u32 {
+            0xC0000005 => Some(("STATUS_ACCESS_VIOLATION", CRASH_HINT)),
+            0xC000001D => Some(("STATUS_ILLEGAL_INSTRUCTION", CRASH_HINT)),
+            0xC00000FD => Some(("STATUS_STACK_OVERFLOW", CRASH_HINT)),
+            0xC0000409 => Some(("STATUS_STACK_BUFFER_OVERRUN", CRASH_HINT)),
+            0xC0000017 => Some(("STATUS_NO_MEMORY", "lua-language-server ran out of memory")),
+            _ => None,
+        };
+        if let Some((name, hint)) = status_name {
+            return (
+                format!("crashed with {name} (0x{:08X})", code as u32),
+                Some(hint),
+            );
+        }
+    }
+    (status.to_string(), None)
+}
+
+/// The name of a common signal, like `SIGSEGV`.
+#[cfg(unix)]
+fn signal_name(signal: libc::c_int) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => return None,
+    })
+}
+
+/// Run the server in its own process group, so that [`kill_tree`] can kill its workers too.
+pub fn isolate(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = cmd;
+    }
+}
+
+/// Limits on the server's resources, for `--nice` and `--memory-limit`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    /// The server's niceness on Unix. On Windows, positive values run it at the below-normal
+    /// priority class and negative values at the above-normal priority class.
+    pub nice: Option<i32>,
+    /// The most memory the server can use, in bytes: its address space on Unix, or the
+    /// memory committed by its job on Windows.
+    pub memory: Option<u64>,
+}
+
+/// Apply `limits` to the server when it starts.
+///
+/// On Windows, the memory limit is applied by [`limit_child`] once the server has started.
+pub fn limit(cmd: &mut Command, limits: Limits) {
+    #[cfg(unix)]
+    if limits.nice.is_some() || limits.memory.is_some() {
+        use std::os::unix::process::CommandExt;
+        // SAFETY: The closure only calls `setpriority` and `setrlimit`, which are
+        // async-signal-safe, between `fork` and `exec`.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(nice) = limits.nice {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(memory) = limits.memory {
+                    let limit = libc::rlimit {
+                        rlim_cur: memory as libc::rlim_t,
+                        rlim_max: memory as libc::rlim_t,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+    #[cfg(windows)]
+    if let Some(nice) = limits.nice.filter(|nice| *nice != 0) {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(if nice > 0 {
+            windows::BELOW_NORMAL_PRIORITY_CLASS
+        } else {
+            windows::ABOVE_NORMAL_PRIORITY_CLASS
+        });
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (cmd, limits);
+    }
+}
+
+/// Apply the limits which can only be applied once the server has started: on Windows, the
+/// memory limit, by assigning the server to a job object.
+///
+/// The server runs briefly before it's assigned, but it doesn't allocate much memory until
+/// it starts checking.
+pub fn limit_child(child: &Child, limits: Limits) -> io::Result<()> {
+    #[cfg(windows)]
+    if let Some(memory) = limits.memory {
+        use std::os::windows::io::AsRawHandle;
+        // SAFETY: The job's limit information is fully initialized, and the child's handle is
+        // valid while it's borrowed. The job's handle is never closed, so the limit lasts as
+        // long as the server.
+        unsafe {
+            let job = windows::CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if job.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let mut info: windows::JobObjectExtendedLimitInformation = std::mem::zeroed();
+            info.basic.limit_flags = windows::JOB_OBJECT_LIMIT_JOB_MEMORY;
+            info.job_memory_limit = usize::try_from(memory).unwrap_or(usize::MAX);
+            let set = windows::SetInformationJobObject(
+                job,
+                windows::JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS,
+                std::ptr::addr_of!(info).cast(),
+                std::mem::size_of_val(&info) as u32,
+            );
+            if set == 0 || windows::AssignProcessToJobObject(job, child.as_raw_handle()) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (child, limits);
+    }
+    Ok(())
+}
+
+/// Wait for a child to exit, returning `None` if it's still running after `timeout`.
+pub fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Kill a child and any processes it started, then wait for it to exit.
+///
+/// On Unix, this kills the child's process group, so the child must have been spawned with
+/// [`isolate`].
+pub fn kill_tree(child: &mut Child) -> io::Result<ExitStatus> {
+    #[cfg(unix)]
+    {
+        // SAFETY: `kill` has no memory-safety preconditions.
+        let result = unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+        if result != 0 {
+            log::debug!(
+                "Failed to kill process group {}: {}",
+                child.id(),
+                io::Error::last_os_error()
+            );
+            child.kill()?;
+        }
+    }
+    #[cfg(windows)]
+    {
+        let status = Command::new("taskkill")
+            .args(["/T", "/F", "/PID"])
+            .arg(child.id().to_string())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        if !matches!(status, Ok(status) if status.success()) {
+            child.kill()?;
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        child.kill()?;
+    }
+    child.wait()
+}
@@ -0,0 +1,192 @@
+use std::io::IsTerminal;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use regex::Regex;
+
+/// How often to print progress when stderr isn't a terminal.
+const NON_TERMINAL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The width of the progress bar, not including the counts.
+const BAR_WIDTH: usize = 30;
+
+/// Re-renders `lua-language-server`'s progress output as a single-line progress bar on stderr.
+///
+/// `lua-language-server --check` draws its progress as `\r>>>>====== 012/123` segments.
+/// Those are parsed and redrawn in place, or printed periodically if stderr isn't a
+/// terminal. Other lines are forwarded unchanged.
+pub struct Progress {
+    is_terminal: bool,
+    /// The current partial segment of output.
+    segment: Vec<u8>,
+    progress_regex: Regex,
+    /// Whether a progress bar is currently drawn.
+    bar_visible: bool,
+    last_printed: Option<Instant>,
+    last_done: Option<usize>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            is_terminal: std::io::stderr().is_terminal(),
+            segment: Vec::new(),
+            progress_regex: Regex::new(r"(\d+)/(\d+)\s*$").expect("Progress regex is valid"),
+            bar_visible: false,
+            last_printed: None,
+            last_done: None,
+        }
+    }
+
+    /// Process a chunk of the server's output.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\r' || byte == b'\n' {
+                let segment = std::mem::take(&mut self.segment);
+                self.segment(&String::from_utf8_lossy(&segment));
+            } else {
+                self.segment.push(byte);
+            }
+        }
+    }
+
+    /// Process any remaining output and clear the progress bar.
+    pub fn finish(&mut self) {
+        let segment = std::mem::take(&mut self.segment);
+        self.segment(&String::from_utf8_lossy(&segment));
+        self.clear();
+    }
+
+    /// Clear the progress bar, if it's drawn.
+    pub fn clear(&mut self) {
+        if self.bar_visible {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+            self.bar_visible = false;
+        }
+    }
+
+    fn segment(&mut self, segment: &str) {
+        let trimmed = segment.trim();
+        if trimmed.is_empty() {
+            return;
+        }
+
+        if let Some((done, total)) = self.parse_progress(trimmed) {
+            self.draw(done, total);
+        } else {
+            self.clear();
+            eprintln!("{trimmed}");
+        }
+    }
+
+    fn parse_progress(&self, segment: &str) -> Option<(usize, usize)> {
+        // Progress segments are a bar of `>` and `=`, followed by the counts.
+        if !segment.starts_with(['>', '=']) {
+            return None;
+        }
+        let captures = self.progress_regex.captures(segment)?;
+        let done = captures[1].parse().ok()?;
+        let total = captures[2].parse().ok()?;
+        Some((done, total))
+    }
+
+    fn draw(&mut self, done: usize, total: usize) {
+        let fraction = if total == 0 {
+            1.0
+        } else {
+            (done as f64 / total as f64).min(1.0)
+        };
+        let percent = (fraction * 100.0).round() as usize;
+
+        let mut stderr = std::io::stderr().lock();
+        if self.is_terminal {
+            let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+            let _ = write!(
+                stderr,
+                "\r\x1b[2K[{}{}] {done}/{total} files ({percent}%)",
+                "=".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+            );
+            let _ = stderr.flush();
+            self.bar_visible = true;
+        } else if self.last_done != Some(done)
+            && (done == total
+                || self
+                    .last_printed
+                    .map(|last| last.elapsed() >= NON_TERMINAL_INTERVAL)
+                    .unwrap_or(true))
+        {
+            let _ = writeln!(stderr, "Checked {done}/{total} files ({percent}%)");
+            self.last_printed = Some(Instant::now());
+            self.last_done = Some(done);
+        }
+    }
+}
+
+/// The number of files `lua-language-server --check` checked, from the last progress segment
+/// in its output, like `>>>>====== 123/123`.
+pub fn files_checked(output: &str) -> Option<usize> {
+    let regex = Regex::new(r"[>=]\s*\d+/(\d+)\s*$").expect("Progress regex is valid");
+    output
+        .rsplit(['\r', '\n'])
+        .find_map(|segment| regex.captures(segment))
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+/// How often to redraw the spinner.
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+const SPINNER_FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// A spinner with the elapsed time, drawn in place on stderr while the server runs.
+///
+/// Only draws when stderr is a terminal.
+pub struct Spinner {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    pub fn start(message: String) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = std::io::stderr().is_terminal().then(|| {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                for frame in SPINNER_FRAMES.iter().cycle() {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let mut stderr = std::io::stderr().lock();
+                    let _ = write!(
+                        stderr,
+                        "\r\x1b[2K{frame} {message} {}s elapsed",
+                        start.elapsed().as_secs()
+                    );
+                    let _ = stderr.flush();
+                    drop(stderr);
+                    std::thread::sleep(SPINNER_INTERVAL);
+                }
+                let mut stderr = std::io::stderr().lock();
+                let _ = write!(stderr, "\r\x1b[2K");
+                let _ = stderr.flush();
+            })
+        });
+        Self { stop, handle }
+    }
+
+    /// Stop and clear the spinner.
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use regex::Regex;
+
+/// How many bytes from the end of each log to remember, to tell if the server appended to
+/// it or rewrote it.
+const TAIL_LEN: u64 = 256;
+
+/// The log files in a `lua-language-server` log directory, so that [`errors`] only reads
+/// what a run logged.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    logs: HashMap<PathBuf, LogState>,
+}
+
+#[derive(Debug)]
+struct LogState {
+    modified: SystemTime,
+    len: u64,
+    tail: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn new(logpath: &Path) -> Self {
+        let logs = log_files(logpath)
+            .filter_map(|(path, modified, len)| {
+                let tail = read_range(&path, len.saturating_sub(TAIL_LEN), len).ok()?;
+                Some((
+                    path,
+                    LogState {
+                        modified,
+                        len,
+                        tail,
+                    },
+                ))
+            })
+            .collect();
+        Self { logs }
+    }
+
+    /// Where this run's entries in a log start.
+    fn start(&self, path: &Path, modified: SystemTime, len: u64) -> Option<u64> {
+        let Some(old) = self.logs.get(path) else {
+            return Some(0);
+        };
+        if old.modified == modified && old.len == len {
+            return None;
+        }
+        let tail_start = old.len.saturating_sub(TAIL_LEN);
+        let appended = old.len <= len
+            && read_range(path, tail_start, old.len).is_ok_and(|tail| tail == old.tail);
+        Some(if appended { old.len } else { 0 })
+    }
+}
+
+/// Find the errors `lua-language-server` logged in `logpath` since `before` was taken.
+///
+/// Entries look like `[12:34:56.789][error] [#0:script/files.lua:123]: message`, sometimes
+/// followed by a stack trace, but the format has changed between releases, so this only
+/// relies on the level appearing in brackets near the start of a line. Only the first line
+/// of each entry is returned.
+pub fn errors(logpath: &Path, before: &Snapshot) -> Vec<String> {
+    let level = Regex::new(r"(?i)^(?:\[[^\]]*\]\s*){0,2}\[(?:error|fatal)\]")
+        .expect("Log level regex is valid");
+
+    let mut logs = log_files(logpath)
+        .filter_map(|(path, modified, len)| {
+            let start = before.start(&path, modified, len)?;
+            Some((path, start, len))
+        })
+        .collect::<Vec<_>>();
+    logs.sort();
+
+    let mut errors = Vec::new();
+    for (log, start, len) in logs {
+        let contents = match read_range(&log, start, len) {
+            Ok(contents) => contents,
+            Err(err) => {
+                log::debug!("Failed to read {}: {err}", log.display());
+                continue;
+            }
+        };
+        log::debug!("Scanning lua-language-server log {}", log.display());
+        errors.extend(
+            String::from_utf8_lossy(&contents)
+                .lines()
+                .filter(|line| level.is_match(line))
+                .map(|line| line.trim_end().to_owned()),
+        );
+    }
+    errors
+}
+
+/// The `.log` files in `logpath`, with their modification times and sizes.
+fn log_files(logpath: &Path) -> impl Iterator<Item = (PathBuf, SystemTime, u64)> {
+    let entries = match std::fs::read_dir(logpath) {
+        Ok(entries) => Some(entries),
+        Err(err) => {
+            log::debug!(
+                "Failed to read lua-language-server log directory {}: {err}",
+                logpath.display()
+            );
+            None
+        }
+    };
+    entries.into_iter().flatten().filter_map(|entry| {
+        let path = entry.ok()?.path();
+        if path.extension()? != "log" {
+            return None;
+        }
+        let metadata = std::fs::metadata(&path).ok()?;
+        Some((path, metadata.modified().ok()?, metadata.len()))
+    })
+}
+
+fn read_range(path: &Path, start: u64, end: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut contents = Vec::new();
+    file.take(end - start).read_to_end(&mut contents)?;
+    Ok(contents)
+}
@@ -0,0 +1,63 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// The lines of source files, read at most once each while rendering diagnostics.
+#[derive(Debug, Clone, Default)]
+pub struct SourceCache {
+    files: RefCell<HashMap<PathBuf, Option<Rc<[String]>>>>,
+}
+
+impl SourceCache {
+    /// The lines of a file, or `None` if it can't be read.
+    pub fn lines(&self, path: &Path) -> Option<Rc<[String]>> {
+        self.files
+            .borrow_mut()
+            .entry(path.to_owned())
+            .or_insert_with(|| match std::fs::read(path) {
+                Ok(contents) => Some(
+                    String::from_utf8_lossy(&contents)
+                        .lines()
+                        .map(ToOwned::to_owned)
+                        .collect(),
+                ),
+                Err(err) => {
+                    log::debug!("Failed to read {}: {err}", path.display());
+                    None
+                }
+            })
+            .clone()
+    }
+}
+
+/// The `FIXME` or `TODO` in a comment on a zero-indexed line or the line above it, for
+/// `--annotate-fixmes`.
+///
+/// `FIXME` takes precedence if there's both.
+pub fn fixme(lines: &[String], line: usize) -> Option<&'static str> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex =
+        REGEX.get_or_init(|| Regex::new(r"\b(?:FIXME|TODO)\b").expect("FIXME regex is valid"));
+    let mut found = None;
+    for text in lines
+        .get(line.saturating_sub(1)..=line)
+        .into_iter()
+        .flatten()
+    {
+        let Some((_, comment)) = text.split_once("--") else {
+            continue;
+        };
+        for tag in regex.find_iter(comment) {
+            if tag.as_str() == "FIXME" {
+                return Some("FIXME");
+            }
+            found = Some("TODO");
+        }
+    }
+    found
+}
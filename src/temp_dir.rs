@@ -0,0 +1,53 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use miette::Context;
+use miette::IntoDiagnostic;
+
+/// A directory for one run's files, deleted when dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    /// Create a new, empty directory in `parent`.
+    pub fn new(parent: &Path) -> miette::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+        let path = parent.join(format!("lualscheck-{}-{nanos:x}", std::process::id()));
+        std::fs::create_dir_all(parent)
+            .and_then(|()| std::fs::create_dir(&path))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create temporary directory: {path:?}"))?;
+        Ok(Self { path, keep: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the directory in place when this is dropped.
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        if let Err(err) = std::fs::remove_dir_all(&self.path) {
+            log::debug!(
+                "Failed to remove temporary directory {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::cell::Cell;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use log::Record;
+use miette::Context;
+use miette::IntoDiagnostic;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+/// The `--trace` recorder, if enabled.
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+/// Records Chrome trace events, viewable in `chrome://tracing` or <https://ui.perfetto.dev/>.
+///
+/// This writes the events itself rather than using `tracing` and `tracing-chrome`, which
+/// can't be added as dependencies yet. [`Span`] mirrors a `tracing` span with fields, so a
+/// later switch only has to replace this module.
+///
+/// See: <https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU>
+struct Recorder {
+    path: PathBuf,
+    start: Instant,
+    events: Mutex<Vec<Value>>,
+}
+
+impl Recorder {
+    fn push(&self, mut event: Map<String, Value>, at: Instant) {
+        event.insert("cat".to_owned(), "lualscheck".into());
+        event.insert(
+            "ts".to_owned(),
+            (at.saturating_duration_since(self.start).as_micros() as u64).into(),
+        );
+        event.insert("pid".to_owned(), std::process::id().into());
+        event.insert("tid".to_owned(), thread_id().into());
+        if let Ok(mut events) = self.events.lock() {
+            events.push(Value::Object(event));
+        }
+    }
+}
+
+/// A small, stable ID for the current thread.
+fn thread_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static ID: Cell<u64> = const { Cell::new(0) };
+    }
+    ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+/// Start recording trace events, to be written to `path` by [`finish`].
+pub fn init(path: &Path) {
+    let _ = RECORDER.set(Recorder {
+        path: path.to_owned(),
+        start: Instant::now(),
+        events: Mutex::new(Vec::new()),
+    });
+}
+
+/// Check if `--trace` is enabled.
+pub fn enabled() -> bool {
+    RECORDER.get().is_some()
+}
+
+/// Write the recorded events to the `--trace` path, if enabled.
+pub fn finish() -> miette::Result<()> {
+    let Some(recorder) = RECORDER.get() else {
+        return Ok(());
+    };
+    let events = recorder
+        .events
+        .lock()
+        .map(|events| events.clone())
+        .unwrap_or_default();
+    let contents = serde_json::to_vec(&json!({
+        "traceEvents": events,
+        "displayTimeUnit": "ms",
+    }))
+    .into_diagnostic()?;
+    std::fs::write(&recorder.path, contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write trace: {:?}", recorder.path))
+}
+
+/// Record a span from `start` until now.
+pub fn complete(name: &str, start: Instant, args: Map<String, Value>) {
+    if let Some(recorder) = RECORDER.get() {
+        let mut event = Map::new();
+        event.insert("name".to_owned(), name.into());
+        event.insert("ph".to_owned(), "X".into());
+        event.insert(
+            "dur".to_owned(),
+            (start.elapsed().as_micros() as u64).into(),
+        );
+        event.insert("args".to_owned(), Value::Object(args));
+        recorder.push(event, start);
+    }
+}
+
+/// Record a log message as an instant event.
+pub fn log(record: &Record) {
+    if let Some(recorder) = RECORDER.get() {
+        let mut event = Map::new();
+        event.insert(
+            "name".to_owned(),
+            format!("{} {}", record.level(), record.target()).into(),
+        );
+        event.insert("ph".to_owned(), "i".into());
+        event.insert("s".to_owned(), "t".into());
+        event.insert(
+            "args".to_owned(),
+            json!({ "message": record.args().to_string() }),
+        );
+        recorder.push(event, Instant::now());
+    }
+}
+
+/// A span recorded when dropped, if `--trace` is enabled.
+pub struct Span {
+    name: &'static str,
+    start: Instant,
+    args: Map<String, Value>,
+}
+
+impl Span {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            start: Instant::now(),
+            args: Map::new(),
+        }
+    }
+
+    /// Attach a value to the span, replacing any previous value for `key`.
+    pub fn arg(&mut self, key: &str, value: impl Into<Value>) {
+        if enabled() {
+            self.args.insert(key.to_owned(), value.into());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        complete(self.name, self.start, std::mem::take(&mut self.args));
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::Serialize;
+use serde::Serializer;
+
+/// A `lua-language-server` version, like `3.7.4`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    /// Find the first version in the output of `lua-language-server --version`.
+    ///
+    /// Releases have printed bare versions like `3.7.4`, as well as prefixed, suffixed, and
+    /// two-part versions like `lua-language-server 3.6.25`, `v3.5.6`, `3.7.4-dev`, and `2.6`.
+    pub fn find(text: &str) -> Option<Self> {
+        let regex = Regex::new(r"(\d+)\.(\d+)(?:\.(\d+))?").expect("Version regex is valid");
+        let captures = regex.captures(text)?;
+        let part = |index: usize| {
+            captures
+                .get(index)
+                .map(|part| part.as_str().parse().ok())
+                .unwrap_or(Some(0))
+        };
+        Some(Self {
+            major: part(1)?,
+            minor: part(2)?,
+            patch: part(3)?,
+        })
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim().trim_start_matches('v');
+        match Self::find(trimmed) {
+            Some(version) if trimmed.starts_with(|c: char| c.is_ascii_digit()) => Ok(version),
+            _ => Err(format!("expected a version like `3.7.4`: {s:?}")),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,75 @@
+//! Hint-level diagnostics are only reported by `lua-language-server` with `--checklevel Hint`,
+//! which `lualscheck` passes when they're shown, fail the run, or may be promoted by
+//! `--severity`.
+#![cfg(unix)]
+
+mod common;
+
+use common::check_hints;
+
+const HINTS: &str = "
+init.lua:1:7-1:13 [unused-local]
+    hint: Unused local `unused`.
+
+init.lua:3:22-3:26 [unused-local]
+    hint: Unused local `name`.
+";
+
+#[test]
+fn test_hints_shown_by_default() {
+    let output = check_hints(&[]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), HINTS);
+    // Hints don't fail the run by default.
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_fail_on_hints() {
+    let output = check_hints(&["--fail", "hint"]);
+    // With a blank line before the error.
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{HINTS}\n")
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("found 2 problems"));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_hints_hidden() {
+    let output = check_hints(&["--show", "info"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_severity_override_promotes_hints() {
+    let output = check_hints(&["--show", "error", "--severity", "unused-local=error"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", HINTS.replace("hint:", "error:"))
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_severity_override_explicit_checklevel() {
+    let output = check_hints(&[
+        "--show",
+        "error",
+        "--checklevel",
+        "warning",
+        "--severity",
+        "unused-local=error",
+    ]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_severity_override_demotes() {
+    // Reclassifying as a hidden severity can't show anything more.
+    let output = check_hints(&["--show", "error", "--severity", "unused-local=info"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert_eq!(output.status.code(), Some(0));
+}
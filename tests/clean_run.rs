@@ -0,0 +1,49 @@
+//! Clean runs print the same empty report in every format, whether or not the server wrote a
+//! diagnostics file.
+#![cfg(unix)]
+
+mod common;
+
+use common::check_hints;
+
+/// Each format's or mode's arguments and its output for a clean run.
+const EMPTY_REPORTS: &[(&[&str], &str)] = &[
+    (&["--format", "json"], "[]\n"),
+    (&["--format", "ndjson"], ""),
+    (
+        &["--format", "csv"],
+        "path,line,column,end_line,end_column,severity,code,message\n",
+    ),
+    (
+        &["--format", "checkstyle"],
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<checkstyle version=\"4.3\">\n</checkstyle>\n",
+    ),
+    (&["--format", "teamcity"], ""),
+    (&["--format", "emacs"], ""),
+    (&["--count"], "0\n"),
+    (&["--top", "3", "--format", "json"], "[]\n"),
+    (&["--summary-by", "dir", "--format", "json"], "[]\n"),
+];
+
+fn check_empty_reports(clean_run: &[&str]) {
+    for (args, expected) in EMPTY_REPORTS {
+        let output = check_hints(&[clean_run, args].concat());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            *expected,
+            "{args:?}"
+        );
+        assert_eq!(output.status.code(), Some(0), "{args:?}");
+    }
+}
+
+#[test]
+fn test_no_diagnostics_file() {
+    // Above hints, the server finds no problems and doesn't write a diagnostics file.
+    check_empty_reports(&["--show", "info"]);
+}
+
+#[test]
+fn test_all_diagnostics_filtered() {
+    check_empty_reports(&["--exclude", "init.lua"]);
+}
@@ -0,0 +1,28 @@
+//! Running `lualscheck` on fixture projects with fake servers.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+
+/// A fixture in `tests/fixtures`, like `hints`.
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Run `lualscheck` on `tests/fixtures/hints/project` with a fake server, which only has
+/// hint-level problems.
+pub fn check_hints(args: &[&str]) -> Output {
+    let fixture = fixture("hints");
+    Command::new(env!("CARGO_BIN_EXE_lualscheck"))
+        .arg("-c")
+        .arg(fixture.join("lua-language-server"))
+        .args(args)
+        .arg(fixture.join("project"))
+        .env("XDG_CACHE_HOME", env!("CARGO_TARGET_TMPDIR"))
+        .env_remove("LUALSCHECK_SERVER")
+        .output()
+        .unwrap()
+}
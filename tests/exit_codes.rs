@@ -0,0 +1,65 @@
+//! `lualscheck` exits with 0 for clean runs, 1 when problems are found, 2 for usage errors, and
+//! 3 when it or the server fails.
+#![cfg(unix)]
+
+mod common;
+
+use std::process::Command;
+
+use common::check_hints;
+use common::fixture;
+
+fn exit_code(args: &[&str]) -> Option<i32> {
+    check_hints(args).status.code()
+}
+
+#[test]
+fn test_clean() {
+    assert_eq!(exit_code(&["--show", "info"]), Some(0));
+    // Hints don't fail the run by default.
+    assert_eq!(exit_code(&[]), Some(0));
+}
+
+#[test]
+fn test_problems_found() {
+    assert_eq!(exit_code(&["--fail", "hint"]), Some(1));
+}
+
+#[test]
+fn test_usage_errors() {
+    for args in [
+        &["--bogus"][..],
+        &["--only", "/"],
+        &["--luarc", "missing.json"],
+        &["--relative-to", "missing"],
+        &["--child-cwd", "missing"],
+        &["--top", "2", "--format", "csv"],
+        &["--summary-by", "dir", "--format", "checkstyle"],
+        &["--diff", "previous.json", "--format", "csv"],
+        &["--", "--logpath=/tmp"],
+    ] {
+        assert_eq!(exit_code(args), Some(2), "{args:?}");
+    }
+}
+
+#[test]
+fn test_usage_errors_before_running_server() {
+    // Reported even though the server doesn't exist.
+    let output = Command::new(env!("CARGO_BIN_EXE_lualscheck"))
+        .args(["-c", "/nonexistent/lua-language-server"])
+        .args(["--top", "2", "--format", "csv"])
+        .arg(fixture("hints").join("project"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_tool_failure() {
+    let output = Command::new(env!("CARGO_BIN_EXE_lualscheck"))
+        .args(["-c", "/nonexistent/lua-language-server"])
+        .arg(fixture("hints").join("project"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+}
@@ -0,0 +1,7 @@
+local unused = 1
+
+local function greet(name)
+    print("Hello!")
+end
+
+greet("world")
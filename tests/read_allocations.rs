@@ -0,0 +1,131 @@
+//! Reading a huge diagnostics file only allocates for the diagnostics it keeps.
+//!
+//! This is its own test binary because the allocator counts every allocation in the process, so
+//! nothing else can run at the same time.
+
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use lualscheck::diagnostics_file;
+use lualscheck::diagnostics_file::Filter;
+
+/// Counts the bytes currently allocated, and the most allocated at once.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+impl CountingAllocator {
+    fn add(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::SeqCst) + size;
+        PEAK.fetch_max(allocated, Ordering::SeqCst);
+    }
+
+    fn remove(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::SeqCst);
+    }
+
+    /// Measure the most bytes allocated at once while running `f`, not counting what was
+    /// already allocated beforehand.
+    fn peak_during<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATED.load(Ordering::SeqCst);
+        PEAK.store(before, Ordering::SeqCst);
+        let result = f();
+        (result, PEAK.load(Ordering::SeqCst) - before)
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::remove(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            Self::remove(layout.size());
+            Self::add(new_size);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Enough files for about 100 MB.
+const FILES: usize = 30_000;
+
+/// Diagnostics per file.
+const DIAGNOSTICS: usize = 16;
+
+fn uri(index: usize) -> String {
+    format!("file:///project/lib/{index}.lua")
+}
+
+/// Write a diagnostics file for [`FILES`] files to `path`, a few KB each.
+fn write_diagnostics_file(path: &Path) -> u64 {
+    let diagnostics = (0..DIAGNOSTICS)
+        .map(|line| {
+            format!(
+                r#"{{"range": {{"start": {{"line": {line}, "character": 0}}, "end": {{"line": {line}, "character": 3}}}}, "severity": 2, "code": "undefined-global", "source": "Lua Diagnostics.", "message": "Undefined global `vim`."}}"#
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut writer = BufWriter::new(File::create(path).unwrap());
+    writer.write_all(b"{").unwrap();
+    for index in 0..FILES {
+        if index > 0 {
+            writer.write_all(b",").unwrap();
+        }
+        write!(writer, "{:?}: [{diagnostics}]", uri(index)).unwrap();
+    }
+    writer.write_all(b"}").unwrap();
+    writer.into_inner().unwrap().metadata().unwrap().len()
+}
+
+#[test]
+fn test_read_huge_file() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("huge-diagnostics.json");
+    let len = write_diagnostics_file(&path);
+
+    let kept = [uri(0), uri(FILES - 1)];
+    let filter = Filter {
+        file: &|uri| kept.iter().any(|kept| kept == uri),
+        diagnostic: &|_| true,
+        strict: true,
+    };
+    let (diagnostics, peak) =
+        CountingAllocator::peak_during(|| diagnostics_file::read(&path, &filter, None));
+    std::fs::remove_file(&path).unwrap();
+
+    let diagnostics = diagnostics.unwrap();
+    assert_eq!(
+        diagnostics.keys().collect::<Vec<_>>(),
+        kept.iter().collect::<Vec<_>>()
+    );
+    assert!(diagnostics.values().all(|file| file.len() == DIAGNOSTICS));
+    // A file's diagnostics and the read buffers, nowhere near the whole file.
+    assert!(
+        peak < 1024 * 1024,
+        "Allocated {peak} bytes at once reading a {len} byte file"
+    );
+}
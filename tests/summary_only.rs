@@ -0,0 +1,42 @@
+//! `--summary-only` replaces the human-readable report with a summary, and `-q` only lowers the
+//! log level.
+#![cfg(unix)]
+
+mod common;
+
+use common::check_hints;
+
+const SUMMARY: &str = "Found 2 hints\nNo problems at or above warning severity\n";
+
+#[test]
+fn test_summary_only() {
+    let output = check_hints(&["--summary-only"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), SUMMARY);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_summary_only_machine_format() {
+    let output = check_hints(&["--summary-only", "--format", "ndjson"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 2, "{stdout}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains(SUMMARY));
+}
+
+#[test]
+fn test_quiet_keeps_report() {
+    for args in [&["-q"][..], &["-qq"]] {
+        let output = check_hints(args);
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("hint: Unused local `unused`."),
+            "{args:?}"
+        );
+    }
+    for format in ["json", "ndjson", "csv", "checkstyle"] {
+        let output = check_hints(&["-q", "--format", format]);
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("Unused local `unused`."),
+            "{format}"
+        );
+    }
+}